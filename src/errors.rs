@@ -1,16 +1,185 @@
-use std::borrow::Cow;
+use alloc::borrow::Cow;
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::fmt;
 use thiserror::Error;
 
+use crate::io;
+use crate::types::{Format, ImageMeta, Limit};
+
 pub type ImageResult<T = ()> = Result<T, ImageError>;
 
 #[derive(Debug, Error)]
 pub enum ImageError {
+    /// The image is corrupt or exceeds `Limits`
     #[error("Corrupt image: {0}")]
-    CorruptImage(Cow<'static, str>),
+    CorruptImage(Corruption),
     #[error("Invalid signature")]
     InvalidSignature,
+    /// `std::io::Error` with `std` feature, otherwise `image_meta::io::Error`
     #[error("IO Error: {0}")]
-    Io(#[from] std::io::Error),
-    #[error("Unsupported format")]
-    Unsupported,
+    Io(#[from] io::Error),
+    #[error("Unsupported format: {0}")]
+    Unsupported(Unsupported),
+}
+
+/// Details of `ImageError::CorruptImage`
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Corruption {
+    /// Chunk, marker or block being parsed
+    pub block: Option<Block>,
+    pub format: Format,
+    pub kind: CorruptionKind,
+    pub message: Cow<'static, str>,
+    /// Position of the reader when the error was detected
+    pub offset: Option<u64>,
+    /// Metadata gathered before the end of a truncated image (e.g. frames counted so far)
+    pub partial: Option<Box<ImageMeta>>,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, strum::Display)]
+pub enum CorruptionKind {
+    /// CRC or other checksum mismatch
+    BadChecksum,
+    /// Value out of range or malformed
+    InvalidField,
+    LimitExceeded(Limit),
+    /// The image ends before the required data
+    Truncated,
+    /// Block in the wrong place, or data after the end of the image (strict mode)
+    UnexpectedBlock,
+    /// Block of unknown type where the type matters
+    UnknownBlock,
+}
+
+/// Unit of the image structure
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Block {
+    /// Chunk name of PNG or RIFF (WebP)
+    Chunk([u8; 4]),
+    /// Block label or extension label of GIF
+    Label(u8),
+    /// Marker of JPEG segment
+    Marker(u8),
+}
+
+/// Details of `ImageError::Unsupported`
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Unsupported {
+    /// Leading bytes of the image (up to 18 bytes)
+    pub signature: Vec<u8>,
+    /// Formats tried in order
+    pub tried: Vec<Format>,
+}
+
+impl Corruption {
+    pub fn new<T: Into<Cow<'static, str>>>(
+        format: Format,
+        kind: CorruptionKind,
+        message: T,
+    ) -> Self {
+        Corruption {
+            block: None,
+            format,
+            kind,
+            message: message.into(),
+            offset: None,
+            partial: None,
+        }
+    }
+
+    pub fn with_block(self, block: Block) -> Self {
+        Corruption {
+            block: Some(block),
+            ..self
+        }
+    }
+
+    pub fn with_offset(self, offset: u64) -> Self {
+        Corruption {
+            offset: Some(offset),
+            ..self
+        }
+    }
+
+    pub fn with_partial(self, meta: ImageMeta) -> Self {
+        Corruption {
+            partial: Some(Box::new(meta)),
+            ..self
+        }
+    }
+}
+
+impl ImageError {
+    /// Returns the details if the image is corrupt
+    pub fn corruption(&self) -> Option<&Corruption> {
+        match self {
+            ImageError::CorruptImage(it) => Some(it),
+            _ => None,
+        }
+    }
+
+    /// Returns the kind of the corruption
+    pub fn corruption_kind(&self) -> Option<CorruptionKind> {
+        self.corruption().map(|it| it.kind)
+    }
+
+    /// Returns true if the image ends before the required data
+    pub fn is_truncated(&self) -> bool {
+        self.corruption_kind() == Some(CorruptionKind::Truncated)
+    }
+
+    /// Returns the metadata gathered before the end of the truncated image
+    ///
+    /// Available if the header was complete (e.g. PNG cut off in the frames).
+    pub fn partial(&self) -> Option<&ImageMeta> {
+        self.corruption().and_then(|it| it.partial.as_deref())
+    }
+}
+
+impl From<Corruption> for ImageError {
+    fn from(corruption: Corruption) -> Self {
+        ImageError::CorruptImage(corruption)
+    }
+}
+
+impl fmt::Display for Corruption {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({}", self.message, self.format)?;
+        if let Some(block) = self.block {
+            write!(f, ", {}", block)?;
+        }
+        if let Some(offset) = self.offset {
+            write!(f, ", offset {}", offset)?;
+        }
+        write!(f, ")")
+    }
+}
+
+impl fmt::Display for Block {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Block::Chunk(name) => {
+                write!(f, "chunk ")?;
+                name.iter()
+                    .try_for_each(|it| write!(f, "{}", char::from(*it).escape_debug()))
+            }
+            Block::Label(label) => write!(f, "block 0x{:02x}", label),
+            Block::Marker(marker) => write!(f, "marker 0xff{:02x}", marker),
+        }
+    }
+}
+
+impl fmt::Display for Unsupported {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "signature")?;
+        for it in &self.signature {
+            write!(f, " {:02x}", it)?;
+        }
+        write!(f, ", tried")?;
+        for (i, it) in self.tried.iter().enumerate() {
+            write!(f, "{}{}", if i == 0 { " " } else { ", " }, it)?;
+        }
+        Ok(())
+    }
 }
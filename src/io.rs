@@ -0,0 +1,63 @@
+//! I/O traits for the loaders
+//!
+//! These are the ones of `std::io` with `std` feature. Without it, minimal equivalents are provided
+//! for `no_std` environments.
+
+use byteorder::ByteOrder;
+
+#[cfg(not(feature = "std"))]
+mod bare;
+
+#[cfg(not(feature = "std"))]
+pub use bare::{BufRead, Cursor, Error, ErrorKind, Read, Result, Seek, SeekFrom, Take};
+#[cfg(feature = "std")]
+pub use std::io::{BufRead, Cursor, Error, ErrorKind, Read, Result, Seek, SeekFrom, Take};
+
+/// Reads numbers in the given byte order (The subset of `byteorder::ReadBytesExt`)
+pub trait ReadExt: Read {
+    fn read_u8(&mut self) -> Result<u8> {
+        let mut buf = [0u8; 1];
+        self.read_exact(&mut buf)?;
+        Ok(buf[0])
+    }
+
+    fn read_u16<T: ByteOrder>(&mut self) -> Result<u16> {
+        let mut buf = [0u8; 2];
+        self.read_exact(&mut buf)?;
+        Ok(T::read_u16(&buf))
+    }
+
+    fn read_i16<T: ByteOrder>(&mut self) -> Result<i16> {
+        let mut buf = [0u8; 2];
+        self.read_exact(&mut buf)?;
+        Ok(T::read_i16(&buf))
+    }
+
+    fn read_u32<T: ByteOrder>(&mut self) -> Result<u32> {
+        let mut buf = [0u8; 4];
+        self.read_exact(&mut buf)?;
+        Ok(T::read_u32(&buf))
+    }
+
+    fn read_i32<T: ByteOrder>(&mut self) -> Result<i32> {
+        let mut buf = [0u8; 4];
+        self.read_exact(&mut buf)?;
+        Ok(T::read_i32(&buf))
+    }
+
+    fn read_u16_into<T: ByteOrder>(&mut self, dst: &mut [u16]) -> Result<()> {
+        for it in dst {
+            *it = self.read_u16::<T>()?;
+        }
+        Ok(())
+    }
+
+    fn read_u32_into<T: ByteOrder>(&mut self, dst: &mut [u32]) -> Result<()> {
+        for it in dst {
+            *it = self.read_u32::<T>()?;
+        }
+        Ok(())
+    }
+}
+
+impl<R: ?Sized + Read> ReadExt for R {}
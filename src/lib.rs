@@ -1,7 +1,12 @@
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
 mod errors;
+pub mod io;
 mod loader;
 mod types;
 
-pub use errors::{ImageError, ImageResult};
+pub use errors::{Block, Corruption, CorruptionKind, ImageError, ImageResult, Unsupported};
 pub use loader::*;
 pub use types::*;
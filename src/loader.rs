@@ -1,67 +1,278 @@
+use alloc::vec;
+use alloc::vec::Vec;
+#[cfg(feature = "std")]
 use std::fs::File;
-use std::io::{BufRead, BufReader, Seek, SeekFrom};
+#[cfg(feature = "std")]
+use std::io::BufReader;
+#[cfg(feature = "std")]
 use std::path::Path;
 
+#[cfg(feature = "async")]
+mod async_io;
 pub mod bmp;
 pub mod gif;
 pub mod hdr;
 pub mod jpeg;
+mod limits;
 pub mod png;
 pub mod qoi;
-mod riff;
+pub mod registry;
+mod resumable;
+pub mod riff;
+pub mod slice;
+pub mod source;
+pub mod stream;
+mod tiff;
 pub mod webp;
 
-use crate::errors::ImageError::InvalidSignature;
+#[cfg(feature = "async")]
+pub use async_io::{load_async, load_async_with_options, load_from_file_async};
+
 use crate::errors::{ImageError, ImageResult};
-use crate::types::{Format, ImageMeta};
-
-macro_rules! try_to_load {
-    ($image_type:ident, $image:ident) => {
-        match $image_type::load($image) {
-            Ok(meta) => return Ok(meta),
-            Err(InvalidSignature) => {
-                $image.seek(SeekFrom::Start(0))?;
-            }
-            otherwise => return otherwise,
+use crate::io::{self, BufRead, Cursor, Read, ReadExt, Seek, SeekFrom};
+use crate::loader::stream::{Status, StreamLoader};
+use crate::types::{Comment, Format, ImageMeta, LoadOptions, Palette};
+
+/// Number of bytes to detect any format
+const SIGNATURE_LENGTH: usize = 18;
+
+pub fn load<R: ?Sized + BufRead + Seek>(image: &mut R) -> ImageResult<ImageMeta> {
+    load_with_options(image, &LoadOptions::default())
+}
+
+/// Loads with `options` (e.g. `Strictness::Strict` for validation)
+///
+/// `load` and the other functions apply `LoadOptions::default()`.
+pub fn load_with_options<R: ?Sized + BufRead + Seek>(
+    image: &mut R,
+    options: &LoadOptions,
+) -> ImageResult<ImageMeta> {
+    registry::loaders(options).load(image, options)
+}
+
+/// Detects the format from the signature at the start of `buffer`
+///
+/// Only the first `SIGNATURE_LENGTH` (18) bytes are inspected, and `None` is returned if the signature is unknown or
+/// `buffer` is too short to tell.
+pub fn detect_format(buffer: &[u8]) -> Option<Format> {
+    detect_format_with_options(buffer, &LoadOptions::default())
+}
+
+/// Detects the format with the loaders of `options` (See `detect_format`)
+pub fn detect_format_with_options(buffer: &[u8], options: &LoadOptions) -> Option<Format> {
+    registry::loaders(options).detect(buffer)
+}
+
+fn detect_builtin_format(buffer: &[u8]) -> Option<Format> {
+    match buffer {
+        [0xff, 0xd8, ..] => Some(Format::Jpeg),
+        [b'G', b'I', b'F', b'8', b'7' | b'9', b'a', ..] => Some(Format::Gif),
+        [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n', ..] => Some(Format::Png),
+        _ if bmp::detect(buffer) => Some(Format::Bmp),
+        [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P', ..] => Some(Format::Webp),
+        [b'#', b'?', b'R', b'A', b'D', b'I', b'A', b'N', b'C', b'E', b'\n', ..] => {
+            Some(Format::Hdr)
         }
-    };
+        [b'q', b'o', b'i', b'f', ..] => Some(Format::Qoi),
+        _ => None,
+    }
 }
 
-pub fn load<R: ?Sized + BufRead + Seek>(image: &mut R) -> ImageResult<ImageMeta> {
-    try_to_load!(jpeg, image);
-    try_to_load!(gif, image);
-    try_to_load!(png, image);
-    try_to_load!(bmp, image);
-    try_to_load!(webp, image);
-    try_to_load!(hdr, image);
-    try_to_load!(qoi, image);
-    Err(ImageError::Unsupported)
+/// Detects the format from the leading bytes of `image` without consuming them
+///
+/// See `detect_format`. The bytes are read until `SIGNATURE_LENGTH` or the end of the image, and
+/// `image` is sought back if they are not buffered at once.
+pub fn detect_format_from_reader<R: ?Sized + BufRead + Seek>(
+    image: &mut R,
+) -> ImageResult<Option<Format>> {
+    let mut signature = [0u8; SIGNATURE_LENGTH];
+    let length = peek_signature(image, &mut signature)?;
+    Ok(detect_format(&signature[..length]))
+}
+
+/// Detects the format, or returns `ImageError::Unsupported`
+fn require_format<R: ?Sized + BufRead + Seek>(
+    image: &mut R,
+    options: &LoadOptions,
+) -> ImageResult<Format> {
+    let mut signature = [0u8; SIGNATURE_LENGTH];
+    let length = peek_signature(image, &mut signature)?;
+    let signature = &signature[..length];
+    detect_format_with_options(signature, options).ok_or_else(|| unsupported(signature, options))
+}
+
+/// Reads the leading bytes of `image` into `signature` without consuming them
+fn peek_signature<R: ?Sized + BufRead + Seek>(
+    image: &mut R,
+    signature: &mut [u8; SIGNATURE_LENGTH],
+) -> ImageResult<usize> {
+    let buffered = image.fill_buf()?;
+    if SIGNATURE_LENGTH <= buffered.len() {
+        signature.copy_from_slice(&buffered[..SIGNATURE_LENGTH]);
+        return Ok(SIGNATURE_LENGTH);
+    }
+
+    let start = image.stream_position()?;
+    let mut length = 0;
+    while length < SIGNATURE_LENGTH {
+        match image.read(&mut signature[length..]) {
+            Ok(0) => break,
+            Ok(n) => length += n,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => (),
+            Err(err) => return Err(err.into()),
+        }
+    }
+    image.seek(SeekFrom::Start(start))?;
+    Ok(length)
+}
+
+fn unsupported(signature: &[u8], options: &LoadOptions) -> ImageError {
+    registry::loaders(options).unsupported(signature)
+}
+
+/// Reads `length` bytes of a block for the walkers
+///
+/// The buffer grows with the bytes actually read, so a bogus length does not allocate beyond the
+/// image.
+fn read_data<R: ?Sized + BufRead>(image: &mut R, length: u64) -> ImageResult<Vec<u8>> {
+    let mut result = vec![];
+    let mut rest = length;
+    while 0 < rest {
+        let data = image.fill_buf()?;
+        if data.is_empty() {
+            return Err(
+                io::Error::new(io::ErrorKind::UnexpectedEof, "Unexpected end of block").into(),
+            );
+        }
+        let n = usize::try_from(rest).map_or(data.len(), |it| it.min(data.len()));
+        result.extend_from_slice(&data[..n]);
+        image.consume(n);
+        rest -= n as u64;
+    }
+    Ok(result)
+}
+
+/// Skips `length` bytes of a block for the walkers
+///
+/// Seeking beyond the end of the image succeeds, so the last byte is read to tell truncation.
+fn skip_data<R: ?Sized + BufRead + Seek>(image: &mut R, length: u64) -> ImageResult {
+    if let Some(rest) = length.checked_sub(1) {
+        image.seek(SeekFrom::Current(i64::try_from(rest).unwrap_or(i64::MAX)))?;
+        image.read_u8()?;
+    }
+    Ok(())
 }
 
 pub fn load_from_buf(buffer: &[u8]) -> ImageResult<ImageMeta> {
-    let mut buffer = std::io::Cursor::new(buffer);
+    let mut buffer = Cursor::new(buffer);
     load(&mut buffer)
 }
 
+/// Loads from a non-seekable reader (e.g. pipes, stdin and decompression streams)
+///
+/// The bytes are pushed to `StreamLoader` in a single forward pass, and the data the loader skips
+/// over is read and discarded.
+pub fn load_from_reader<R: ?Sized + Read>(image: &mut R) -> ImageResult<ImageMeta> {
+    load_from_reader_with_options(image, &LoadOptions::default())
+}
+
+pub fn load_from_reader_with_options<R: ?Sized + Read>(
+    image: &mut R,
+    options: &LoadOptions,
+) -> ImageResult<ImageMeta> {
+    let mut loader = StreamLoader::with_options(options.clone());
+    let mut buffer = [0u8; 8192];
+
+    loop {
+        // Read the skipped data into the buffer only to discard
+        let skippable = loader.skippable();
+        let limit = match skippable {
+            0 => buffer.len(),
+            it => it.min(buffer.len() as u64) as usize,
+        };
+        let buffer = &mut buffer[..limit];
+
+        let length = match image.read(buffer) {
+            Ok(0) => return loader.finish(),
+            Ok(length) => length,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err.into()),
+        };
+        if 0 < skippable {
+            loader.skip(length as u64)?;
+            continue;
+        }
+        if let Status::Done(meta) = loader.feed(&buffer[..length])? {
+            return Ok(meta);
+        }
+    }
+}
+
+#[cfg(feature = "std")]
 pub fn load_from_file<T: ?Sized + AsRef<Path>>(file: &T) -> ImageResult<ImageMeta> {
+    load_from_file_with_options(file, &LoadOptions::default())
+}
+
+#[cfg(feature = "std")]
+pub fn load_from_file_with_options<T: ?Sized + AsRef<Path>>(
+    file: &T,
+    options: &LoadOptions,
+) -> ImageResult<ImageMeta> {
     let file = File::open(file.as_ref())?;
     let mut file = BufReader::new(file);
-    load(&mut file)
+    load_with_options(&mut file, options)
+}
+
+/// Returns the comments of the image (GIF, HDR, JPEG and PNG)
+pub fn load_comments<R: ?Sized + BufRead + Seek>(image: &mut R) -> ImageResult<Vec<Comment>> {
+    load_comments_with_options(image, &LoadOptions::default())
+}
+
+pub fn load_comments_with_options<R: ?Sized + BufRead + Seek>(
+    image: &mut R,
+    options: &LoadOptions,
+) -> ImageResult<Vec<Comment>> {
+    let format = require_format(image, options)?;
+    match format {
+        Format::Gif => gif::load_comments_with_options(image, options),
+        Format::Hdr => hdr::load_comments_with_options(image, options),
+        Format::Jpeg => jpeg::load_comments_with_options(image, options),
+        Format::Png => png::load_comments_with_options(image, options),
+        _ => Ok(vec![]),
+    }
+}
+
+/// Returns the palettes of the image (BMP, GIF and PNG)
+pub fn load_palettes<R: ?Sized + BufRead + Seek>(image: &mut R) -> ImageResult<Vec<Palette>> {
+    load_palettes_with_options(image, &LoadOptions::default())
+}
+
+pub fn load_palettes_with_options<R: ?Sized + BufRead + Seek>(
+    image: &mut R,
+    options: &LoadOptions,
+) -> ImageResult<Vec<Palette>> {
+    let format = require_format(image, options)?;
+    match format {
+        Format::Bmp => Ok(bmp::load_palette(image)?.into_iter().collect()),
+        Format::Gif => gif::load_palettes_with_options(image, options),
+        Format::Png => Ok(png::load_palette_with_options(image, options)?
+            .into_iter()
+            .collect()),
+        _ => Ok(vec![]),
+    }
 }
 
 pub fn load_with_format<R: ?Sized + BufRead + Seek>(
     image: &mut R,
     format: Format,
 ) -> ImageResult<ImageMeta> {
-    use Format::*;
+    load_with_format_and_options(image, format, &LoadOptions::default())
+}
 
-    match format {
-        Bmp => bmp::load(image),
-        Gif => gif::load(image),
-        Jpeg => jpeg::load(image),
-        Png => png::load(image),
-        Qoi => qoi::load(image),
-        Webp => webp::load(image),
-        Hdr => hdr::load(image),
-    }
+pub fn load_with_format_and_options<R: ?Sized + BufRead + Seek>(
+    image: &mut R,
+    format: Format,
+    options: &LoadOptions,
+) -> ImageResult<ImageMeta> {
+    registry::loaders(options).load_with_format(image, format, options)
 }
@@ -0,0 +1,59 @@
+use std::io::SeekFrom;
+use std::path::Path;
+
+use tokio::fs::File;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncSeek, AsyncSeekExt, BufReader};
+
+use crate::errors::ImageResult;
+use crate::loader::stream::{Status, StreamLoader};
+use crate::types::{ImageMeta, LoadOptions};
+
+/// Async version of `load`
+///
+/// The image is read through `StreamLoader`. The skipped data is consumed if it is buffered, and
+/// sought over otherwise.
+pub async fn load_async<R: ?Sized + AsyncBufRead + AsyncSeek + Unpin>(
+    image: &mut R,
+) -> ImageResult<ImageMeta> {
+    load_async_with_options(image, &LoadOptions::default()).await
+}
+
+/// Async version of `load_with_options`
+pub async fn load_async_with_options<R: ?Sized + AsyncBufRead + AsyncSeek + Unpin>(
+    image: &mut R,
+    options: &LoadOptions,
+) -> ImageResult<ImageMeta> {
+    let mut loader = StreamLoader::with_options(options.clone());
+
+    loop {
+        let data = image.fill_buf().await?;
+        if data.is_empty() {
+            return loader.finish();
+        }
+        let length = data.len();
+
+        let skippable = loader.skippable();
+        if 0 < skippable && skippable < length as u64 {
+            image.consume(skippable as usize);
+            loader.skip(skippable)?;
+        } else if 0 < skippable {
+            // The rest is skipped in the next round
+            let offset = i64::try_from(skippable).unwrap_or(i64::MAX);
+            image.seek(SeekFrom::Current(offset)).await?;
+            loader.skip(offset as u64)?;
+        } else {
+            let status = loader.feed(data)?;
+            image.consume(length);
+            if let Status::Done(meta) = status {
+                return Ok(meta);
+            }
+        }
+    }
+}
+
+/// Async version of `load_from_file`
+pub async fn load_from_file_async<T: ?Sized + AsRef<Path>>(file: &T) -> ImageResult<ImageMeta> {
+    let file = File::open(file.as_ref()).await?;
+    let mut file = BufReader::new(file);
+    load_async(&mut file).await
+}
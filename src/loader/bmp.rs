@@ -1,75 +1,523 @@
-use std::io::{BufRead, Seek, SeekFrom};
+use alloc::borrow::Cow;
+use alloc::format;
+use alloc::vec;
+use alloc::vec::Vec;
 
-use byteorder::{LittleEndian, ReadBytesExt};
+use byteorder::{ByteOrder, LittleEndian};
 
-use crate::errors::{ImageError, ImageResult};
-use crate::types::{Color, ColorMode, Dimensions, Format, ImageMeta};
+use crate::errors::{Corruption, CorruptionKind, ImageError, ImageResult};
+use crate::io::{BufRead, Cursor, ReadExt, Seek, SeekFrom};
+use crate::loader::limits::{limited, Context};
+use crate::loader::resumable::run;
+use crate::loader::{jpeg, png};
+use crate::types::{
+    Color, ColorMode, ColorSpace, Dimensions, Format, ImageMeta, LoadOptions, Palette,
+    PaletteEntry, PaletteSource, Transfer,
+};
+
+/// Bitmap information header of BMP
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Header {
+    pub bits_per_pixel: u16,
+    /// Only for V4 and V5 headers
+    pub color_space_type: Option<ColorSpaceType>,
+    /// Number of entries in the color table (0 means the maximum for the bit depth)
+    pub colors_used: u32,
+    pub compression: Compression,
+    /// Offset of the pixel data from the beginning of the file
+    pub data_offset: u32,
+    pub dimensions: Dimensions,
+    pub file_type: FileType,
+    /// Only for BITFIELDS and ALPHABITFIELDS
+    pub masks: Option<Masks>,
+    /// Rows are stored from top to bottom (negative height)
+    pub top_down: bool,
+    pub version: Version,
+}
+
+/// Signature of the file header
+#[derive(Clone, Copy, Debug, Eq, PartialEq, strum::Display)]
+pub enum FileType {
+    /// `BM`
+    Bitmap,
+    /// `CI` (OS/2)
+    ColorIcon,
+    /// `CP` (OS/2)
+    ColorPointer,
+    /// `IC` (OS/2)
+    Icon,
+    /// `PT` (OS/2)
+    Pointer,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, strum::Display)]
+pub enum Version {
+    /// BITMAPCOREHEADER / OS21XBITMAPHEADER (12 bytes)
+    Core,
+    /// BITMAPINFOHEADER (40 bytes)
+    Info,
+    /// OS22XBITMAPHEADER (16 or 64 bytes)
+    Os2,
+    /// BITMAPV2INFOHEADER (52 bytes)
+    V2,
+    /// BITMAPV3INFOHEADER (56 bytes)
+    V3,
+    /// BITMAPV4HEADER (108 bytes)
+    V4,
+    /// BITMAPV5HEADER (124 bytes)
+    V5,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, strum::Display)]
+pub enum Compression {
+    AlphaBitfields,
+    Bitfields,
+    Cmyk,
+    CmykRle4,
+    CmykRle8,
+    /// OS/2 only
+    Huffman1D,
+    Jpeg,
+    Png,
+    Rgb,
+    /// OS/2 only
+    Rle24,
+    Rle4,
+    Rle8,
+    Unknown(u32),
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Masks {
+    pub alpha: u32,
+    pub blue: u32,
+    pub green: u32,
+    pub red: u32,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, strum::Display)]
+pub enum ColorSpaceType {
+    /// LCS_CALIBRATED_RGB (with endpoints and gamma)
+    CalibratedRgb,
+    /// PROFILE_EMBEDDED
+    Embedded,
+    /// PROFILE_LINKED
+    Linked,
+    /// LCS_sRGB
+    Srgb,
+    /// LCS_WINDOWS_COLOR_SPACE
+    Windows,
+    Unknown(u32),
+}
 
 pub fn load<R: ?Sized + BufRead + Seek>(image: &mut R) -> ImageResult<ImageMeta> {
-    read_signature(image)?;
-    let (dimensions, color) = read_header(image)?;
+    load_with_options(image, &LoadOptions::default())
+}
+
+pub fn load_with_options<R: ?Sized + BufRead + Seek>(
+    image: &mut R,
+    options: &LoadOptions,
+) -> ImageResult<ImageMeta> {
+    limited(image, Format::Bmp, options, |image, cx| {
+        let start = image.stream_position()?;
+        let header = load_header(image)?;
+        cx.dimensions(&header.dimensions)?;
+        to_meta(image, start, &header, cx)
+    })
+}
+
+/// Returns true if `buffer` starts with a file header followed by a bitmap header of known size
+///
+/// The 2 bytes signatures alone are likely to appear in other files (e.g. texts). OS/2 bitmap
+/// arrays are told by the signature of the first bitmap following the 14 bytes of the array header.
+pub(crate) fn detect(buffer: &[u8]) -> bool {
+    let signature = |offset: usize| buffer.get(offset..offset + 2);
+    match signature(0) {
+        Some(b"BA") => matches!(signature(14), Some(b"BM" | b"CI" | b"CP" | b"IC" | b"PT")),
+        Some(b"BM" | b"CI" | b"CP" | b"IC" | b"PT") => matches!(
+            buffer.get(14..18).map(LittleEndian::read_u32),
+            Some(12 | 16 | 40 | 52 | 56 | 64 | 108 | 124)
+        ),
+        _ => false,
+    }
+}
+
+/// Loads the first image if the file is an OS/2 bitmap array
+pub fn load_header<R: ?Sized + BufRead + Seek>(image: &mut R) -> ImageResult<Header> {
+    let mut signature = read_signature(image)?;
+    if signature == *b"BA" {
+        // 4 Size
+        // 4 Offset to next
+        // 2 Display width
+        // 2 Display height
+        image.seek(SeekFrom::Current(12))?;
+        signature = read_signature(image)?;
+    }
+    read_bitmap(image, signature)
+}
+
+/// Loads all images of OS/2 bitmap array (or the single image of other files)
+pub fn load_array<R: ?Sized + BufRead + Seek>(image: &mut R) -> ImageResult<Vec<ImageMeta>> {
+    load_array_with_options(image, &LoadOptions::default())
+}
+
+pub fn load_array_with_options<R: ?Sized + BufRead + Seek>(
+    image: &mut R,
+    options: &LoadOptions,
+) -> ImageResult<Vec<ImageMeta>> {
+    limited(image, Format::Bmp, options, |image, cx| {
+        let start = image.stream_position()?;
+        let mut result = vec![];
+        loop {
+            cx.block()?;
+            let signature = read_signature(image)?;
+            if signature != *b"BA" {
+                let header = read_bitmap(image, signature)?;
+                result.push(to_meta(image, start, &header, cx)?);
+                return Ok(result);
+            }
+
+            image.seek(SeekFrom::Current(4))?; // size
+            let next = image.read_u32::<LittleEndian>()?;
+            image.seek(SeekFrom::Current(4))?; // display width and height
+            let signature = read_signature(image)?;
+            let header = read_bitmap(image, signature)?;
+            result.push(to_meta(image, start, &header, cx)?);
+
+            // Offsets are from the beginning of the file
+            let next = start + u64::from(next);
+            if next < image.stream_position()? {
+                return Ok(result);
+            }
+            image.seek(SeekFrom::Start(next))?;
+        }
+    })
+}
+
+/// Returns the color table of the indexed image
+pub fn load_palette<R: ?Sized + BufRead + Seek>(image: &mut R) -> ImageResult<Option<Palette>> {
+    let header = load_header(image)?;
+    if !header.is_indexed() {
+        return Ok(None);
+    }
+
+    // The color table follows the header (and the masks)
+    let max = 1 << header.bits_per_pixel;
+    let size = match header.colors_used {
+        0 => max,
+        n => n.min(max),
+    } as usize;
+    let entry_size = if header.version == Version::Core {
+        3
+    } else {
+        4
+    };
+    let mut table = [0u8; 256 * 4];
+    let table = &mut table[..size * entry_size];
+    image.read_exact(table)?;
+
+    let entries = table
+        .chunks_exact(entry_size)
+        .map(|it| PaletteEntry {
+            blue: it[0],
+            green: it[1],
+            red: it[2],
+            alpha: 255,
+        })
+        .collect();
+
+    Ok(Some(Palette {
+        entries,
+        source: PaletteSource::Bmp,
+    }))
+}
+
+fn to_meta<R: ?Sized + BufRead + Seek>(
+    image: &mut R,
+    start: u64,
+    header: &Header,
+    cx: &mut Context,
+) -> ImageResult<ImageMeta> {
+    type Load<R> = fn(&mut R, &mut Context) -> ImageResult<ImageMeta>;
+
+    // BI_JPEG and BI_PNG contain a whole image as the pixel data, loaded within the limits of the
+    // bitmap
+    let embedded = match header.compression {
+        Compression::Jpeg => Some(run::<jpeg::MetaReader, R> as Load<R>),
+        Compression::Png => Some(run::<png::MetaReader, R> as Load<R>),
+        _ => None,
+    };
+    if let Some(load) = embedded {
+        image.seek(SeekFrom::Start(start + u64::from(header.data_offset)))?;
+        let meta = load(image, cx).map_err(|err| match err {
+            ImageError::InvalidSignature => {
+                corrupt(CorruptionKind::InvalidField, "Invalid embedded image").into()
+            }
+            err => err,
+        })?;
+        return Ok(ImageMeta {
+            format: Format::Bmp,
+            ..meta
+        });
+    }
+
+    let color = if header.is_indexed() {
+        Color {
+            alpha_channel: false,
+            mode: ColorMode::Indexed,
+            resolution: header.bits_per_pixel as u8,
+        }
+    } else {
+        Color {
+            alpha_channel: header.masks.is_some_and(|it| it.alpha != 0),
+            mode: ColorMode::Rgb,
+            resolution: (header.bits_per_pixel / 3) as u8,
+        }
+    };
+    let color_space = match header.color_space_type {
+        Some(ColorSpaceType::Srgb | ColorSpaceType::Windows) => Some(ColorSpace {
+            transfer: Some(Transfer::Srgb),
+            ..ColorSpace::default()
+        }),
+        _ => None,
+    };
 
     Ok(ImageMeta {
         animation_frames: None,
         color,
-        dimensions,
+        color_space,
+        dimensions: header.dimensions.clone(),
         format: Format::Bmp,
     })
 }
 
-fn read_signature<R: ?Sized + BufRead + Seek>(image: &mut R) -> ImageResult {
+fn read_signature<R: ?Sized + BufRead + Seek>(image: &mut R) -> ImageResult<[u8; 2]> {
     let mut signature = [0u8; 2];
     image.read_exact(&mut signature)?;
-    if signature != *b"BM" {
-        return Err(ImageError::InvalidSignature);
+    match &signature {
+        b"BM" | b"BA" | b"CI" | b"CP" | b"IC" | b"PT" => Ok(signature),
+        _ => Err(ImageError::InvalidSignature),
     }
-    // Skip rest file header
-    image.seek(SeekFrom::Current(12))?;
-    Ok(())
 }
 
-fn read_header<R: ?Sized + BufRead + Seek>(image: &mut R) -> ImageResult<(Dimensions, Color)> {
-    let header_size = image.read_u32::<LittleEndian>()?;
-    match header_size {
-        12 | 64 => read_os2_header(image),
-        40 | 108 | 124 => read_windows_header(image),
-        sz => Err(ImageError::CorruptImage(
-            format!("Unsupported header size: {}", sz).into(),
-        )),
+/// Reads the file header following the signature and the bitmap header
+fn read_bitmap<R: ?Sized + BufRead + Seek>(
+    image: &mut R,
+    signature: [u8; 2],
+) -> ImageResult<Header> {
+    let file_type = match &signature {
+        b"BM" => FileType::Bitmap,
+        b"CI" => FileType::ColorIcon,
+        b"CP" => FileType::ColorPointer,
+        b"IC" => FileType::Icon,
+        b"PT" => FileType::Pointer,
+        _ => return Err(corrupt(CorruptionKind::UnknownBlock, "Invalid bitmap in array").into()),
+    };
+
+    // 4 File size
+    // 4 Reserved (Hotspot for icons and pointers)
+    image.seek(SeekFrom::Current(8))?;
+    let data_offset = image.read_u32::<LittleEndian>()?;
+    let mut header = read_header(image, file_type, data_offset)?;
+
+    match file_type {
+        FileType::Bitmap => (),
+        // AND mask and XOR mask
+        FileType::Icon | FileType::Pointer => header.dimensions.height /= 2,
+        FileType::ColorIcon | FileType::ColorPointer => {
+            // The first bitmap is the monochrome mask, the color bitmap follows its color table
+            let entry_size = if header.version == Version::Core {
+                3
+            } else {
+                4
+            };
+            image.seek(SeekFrom::Current(entry_size * 2))?;
+            if read_signature(image)? != signature {
+                return Err(corrupt(CorruptionKind::UnknownBlock, "Color bitmap not found").into());
+            }
+            image.seek(SeekFrom::Current(8))?;
+            let data_offset = image.read_u32::<LittleEndian>()?;
+            header = read_header(image, file_type, data_offset)?;
+        }
     }
+
+    Ok(header)
 }
 
-fn read_windows_header<R: ?Sized + BufRead + Seek>(
+fn read_header<R: ?Sized + BufRead + Seek>(
     image: &mut R,
-) -> ImageResult<(Dimensions, Color)> {
-    let width = image.read_u32::<LittleEndian>()?;
-    let height = image.read_i32::<LittleEndian>()?.unsigned_abs();
-    image.seek(SeekFrom::Current(2))?; // planes
-
-    let resolution = image.read_u16::<LittleEndian>()? / 3;
-    let dimensions = Dimensions { height, width };
-    let color = Color {
-        alpha_channel: false,
-        mode: ColorMode::Rgb,
-        resolution: resolution as u8,
+    file_type: FileType,
+    data_offset: u32,
+) -> ImageResult<Header> {
+    let header_size = image.read_u32::<LittleEndian>()?;
+    let version = match header_size {
+        12 => Version::Core,
+        16 | 64 => Version::Os2,
+        40 => Version::Info,
+        52 => Version::V2,
+        56 => Version::V3,
+        108 => Version::V4,
+        124 => Version::V5,
+        // Two bytes signatures of OS/2 are likely to appear in other files
+        _ if file_type != FileType::Bitmap => return Err(ImageError::InvalidSignature),
+        sz => {
+            return Err(corrupt(
+                CorruptionKind::InvalidField,
+                format!("Unsupported header size: {}", sz),
+            )
+            .into())
+        }
     };
 
-    Ok((dimensions, color))
-}
+    let mut data = [0u8; 120];
+    let data = &mut data[..header_size as usize - 4];
+    image.read_exact(data)?;
+    let mut data = Cursor::new(data);
 
-fn read_os2_header<R: ?Sized + BufRead + Seek>(image: &mut R) -> ImageResult<(Dimensions, Color)> {
-    let width = image.read_u16::<LittleEndian>().map(u32::from)?;
-    let height = image.read_i16::<LittleEndian>()?.unsigned_abs() as u32;
-    image.seek(SeekFrom::Current(2))?; // planes
+    if version == Version::Core {
+        return read_core_header(&mut data, file_type, data_offset);
+    }
+
+    let width = data.read_u32::<LittleEndian>()?;
+    let height = data.read_i32::<LittleEndian>()?;
+    data.seek(SeekFrom::Current(2))?; // planes
+    let bits_per_pixel = data.read_u16::<LittleEndian>()?;
+
+    // The short OS/2 header ends here
+    let (compression, colors_used) = if 16 < header_size {
+        let compression = data.read_u32::<LittleEndian>()?;
+        // 4 Image size
+        // 4 Horizontal resolution
+        // 4 Vertical resolution
+        data.seek(SeekFrom::Current(12))?;
+        let colors_used = data.read_u32::<LittleEndian>()?;
+        (read_compression(compression, version), colors_used)
+    } else {
+        (Compression::Rgb, 0)
+    };
 
-    let resolution = image.read_u16::<LittleEndian>()? / 3;
+    let masks = match compression {
+        Compression::Bitfields | Compression::AlphaBitfields if version == Version::Info => {
+            // The masks follow the header
+            let mut masks = Masks {
+                red: image.read_u32::<LittleEndian>()?,
+                green: image.read_u32::<LittleEndian>()?,
+                blue: image.read_u32::<LittleEndian>()?,
+                alpha: 0,
+            };
+            if compression == Compression::AlphaBitfields {
+                masks.alpha = image.read_u32::<LittleEndian>()?;
+            }
+            Some(masks)
+        }
+        Compression::Bitfields | Compression::AlphaBitfields if version != Version::Os2 => {
+            // 4 Important colors
+            data.seek(SeekFrom::Start(36))?;
+            Some(Masks {
+                red: data.read_u32::<LittleEndian>()?,
+                green: data.read_u32::<LittleEndian>()?,
+                blue: data.read_u32::<LittleEndian>()?,
+                alpha: if version == Version::V2 {
+                    0
+                } else {
+                    data.read_u32::<LittleEndian>()?
+                },
+            })
+        }
+        _ => None,
+    };
 
-    let dimensions = Dimensions { height, width };
-    let color = Color {
-        alpha_channel: false,
-        mode: ColorMode::Rgb,
-        resolution: resolution as u8,
+    let color_space_type = if matches!(version, Version::V4 | Version::V5) {
+        data.seek(SeekFrom::Start(52))?;
+        Some(read_color_space_type(data.read_u32::<LittleEndian>()?))
+    } else {
+        None
     };
 
-    Ok((dimensions, color))
+    Ok(Header {
+        bits_per_pixel,
+        color_space_type,
+        colors_used,
+        compression,
+        data_offset,
+        dimensions: Dimensions {
+            width,
+            height: height.unsigned_abs(),
+        },
+        file_type,
+        masks,
+        top_down: height < 0,
+        version,
+    })
+}
+
+fn read_core_header<R: ?Sized + BufRead + Seek>(
+    data: &mut R,
+    file_type: FileType,
+    data_offset: u32,
+) -> ImageResult<Header> {
+    let width = data.read_u16::<LittleEndian>().map(u32::from)?;
+    let height = data.read_i16::<LittleEndian>()?;
+    data.seek(SeekFrom::Current(2))?; // planes
+    let bits_per_pixel = data.read_u16::<LittleEndian>()?;
+
+    Ok(Header {
+        bits_per_pixel,
+        color_space_type: None,
+        colors_used: 0,
+        compression: Compression::Rgb,
+        data_offset,
+        dimensions: Dimensions {
+            width,
+            height: u32::from(height.unsigned_abs()),
+        },
+        file_type,
+        masks: None,
+        top_down: height < 0,
+        version: Version::Core,
+    })
+}
+
+impl Header {
+    /// Returns true if the pixels are indices of the color table
+    pub fn is_indexed(&self) -> bool {
+        (1..=8).contains(&self.bits_per_pixel)
+    }
+}
+
+fn read_compression(value: u32, version: Version) -> Compression {
+    use Compression::*;
+
+    match (value, version) {
+        (0, _) => Rgb,
+        (1, _) => Rle8,
+        (2, _) => Rle4,
+        (3, Version::Os2) => Huffman1D,
+        (3, _) => Bitfields,
+        (4, Version::Os2) => Rle24,
+        (4, _) => Jpeg,
+        (5, _) => Png,
+        (6, _) => AlphaBitfields,
+        (11, _) => Cmyk,
+        (12, _) => CmykRle8,
+        (13, _) => CmykRle4,
+        (value, _) => Unknown(value),
+    }
+}
+
+fn read_color_space_type(value: u32) -> ColorSpaceType {
+    use ColorSpaceType::*;
+
+    match &value.to_be_bytes() {
+        [0, 0, 0, 0] => CalibratedRgb,
+        b"MBED" => Embedded,
+        b"LINK" => Linked,
+        b"sRGB" => Srgb,
+        b"Win " => Windows,
+        _ => Unknown(value),
+    }
+}
+
+fn corrupt<T: Into<Cow<'static, str>>>(kind: CorruptionKind, message: T) -> Corruption {
+    Corruption::new(Format::Bmp, kind, message)
 }
@@ -1,130 +1,593 @@
-use std::io::{BufRead, Seek, SeekFrom};
+use alloc::borrow::Cow;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
 
-use byteorder::{LittleEndian, ReadBytesExt};
+use byteorder::LittleEndian;
 
-use crate::errors::{ImageError, ImageResult};
-use crate::types::{Color, ColorMode, Dimensions, Format, ImageMeta};
+use crate::errors::{self, Corruption, CorruptionKind, ImageError, ImageResult};
+use crate::io::{BufRead, ReadExt, Seek, SeekFrom};
+use crate::loader::limits::{complete, limited, resume, truncated_block, Context, Usage};
+use crate::loader::resumable::{run, Resumable};
+use crate::types::{
+    Color, ColorMode, Comment, CommentSource, Dimensions, Format, ImageMeta, LoadOptions, Palette,
+    PaletteEntry, PaletteSource,
+};
 
-#[derive(Default)]
+/// Header and logical screen descriptor of GIF
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Header {
+    pub background_color_index: u8,
+    /// Bits per primary color of the original image
+    pub color_resolution: u8,
+    pub dimensions: Dimensions,
+    /// Number of entries of the global color table
+    pub global_color_table: Option<usize>,
+    /// The global color table is sorted by importance
+    pub global_color_table_sorted: bool,
+    /// Raw value, see `Header::aspect_ratio`
+    pub pixel_aspect_ratio: u8,
+    pub version: Version,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, strum::Display)]
+pub enum Version {
+    Gif87a,
+    Gif89a,
+}
+
+/// Block found by `blocks`
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Block {
+    /// Extension label (e.g. 0xf9 for graphic control) if the block is an extension
+    pub extension: Option<u8>,
+    /// 0x21 (extension), 0x2c (image descriptor) or 0x3b (trailer)
+    pub label: u8,
+    /// Length of the block excluding the label, including the sub-blocks
+    pub length: u64,
+    /// Offset of the label in the image
+    pub offset: u64,
+}
+
+/// Iterator over the blocks up to the trailer, see `blocks`
+pub struct Blocks<'a, R: ?Sized> {
+    done: bool,
+    image: &'a mut R,
+    options: LoadOptions,
+    usage: Usage,
+}
+
+#[derive(Clone, Default)]
 struct BlockReader {
+    /// Collects comment extensions if some
+    comments: Option<Vec<Comment>>,
     frames: usize,
+    /// Collects local color tables if some
+    palettes: Option<Vec<Palette>>,
+    transparent_index: Option<u8>,
+}
+
+/// State of `load` between the blocks, kept for truncated images
+#[derive(Clone)]
+pub(crate) struct MetaReader {
+    blocks: BlockReader,
+    dimensions: Dimensions,
 }
 
 pub fn load<R: ?Sized + BufRead + Seek>(image: &mut R) -> ImageResult<ImageMeta> {
-    read_signature(image)?;
-    let (dimensions, color) = read_header(image)?;
+    load_with_options(image, &LoadOptions::default())
+}
 
-    let mut reader = BlockReader::default();
-    reader.read(image)?;
+pub fn load_with_options<R: ?Sized + BufRead + Seek>(
+    image: &mut R,
+    options: &LoadOptions,
+) -> ImageResult<ImageMeta> {
+    limited(image, Format::Gif, options, |image, cx| {
+        run::<MetaReader, _>(image, cx)
+    })
+}
 
-    Ok(ImageMeta {
-        animation_frames: if 1 < reader.frames {
-            Some(reader.frames)
-        } else {
-            None
-        },
-        color,
-        dimensions,
-        format: Format::Gif,
+pub fn load_header<R: ?Sized + BufRead + Seek>(image: &mut R) -> ImageResult<Header> {
+    let version = read_signature(image)?;
+    read_header(image, version)
+}
+
+/// Returns the entries of the global color table
+pub fn load_palette<R: ?Sized + BufRead + Seek>(
+    image: &mut R,
+) -> ImageResult<Option<Vec<PaletteEntry>>> {
+    let header = load_header(image)?;
+    header
+        .global_color_table
+        .map(|size| read_color_table(image, size))
+        .transpose()
+}
+
+/// Returns the texts of comment extensions
+pub fn load_comments<R: ?Sized + BufRead + Seek>(image: &mut R) -> ImageResult<Vec<Comment>> {
+    load_comments_with_options(image, &LoadOptions::default())
+}
+
+pub fn load_comments_with_options<R: ?Sized + BufRead + Seek>(
+    image: &mut R,
+    options: &LoadOptions,
+) -> ImageResult<Vec<Comment>> {
+    limited(image, Format::Gif, options, |image, cx| {
+        let header = load_header(image)?;
+        skip_color_table(image, header.global_color_table)?;
+
+        let mut reader = BlockReader {
+            comments: Some(vec![]),
+            ..BlockReader::default()
+        };
+        reader.read(image, cx)?;
+
+        Ok(reader.comments.unwrap_or_default())
+    })
+}
+
+/// Returns the global color table and the local color tables of the frames
+///
+/// The transparent color index of the graphic control extension is applied to the local color table
+/// of the frame. A frame with the index but without a local color table gets a copy of the global
+/// color table with the index applied (`PaletteSource::GifFrame`).
+pub fn load_palettes<R: ?Sized + BufRead + Seek>(image: &mut R) -> ImageResult<Vec<Palette>> {
+    load_palettes_with_options(image, &LoadOptions::default())
+}
+
+pub fn load_palettes_with_options<R: ?Sized + BufRead + Seek>(
+    image: &mut R,
+    options: &LoadOptions,
+) -> ImageResult<Vec<Palette>> {
+    limited(image, Format::Gif, options, |image, cx| {
+        let header = load_header(image)?;
+
+        let mut palettes = vec![];
+        if let Some(size) = header.global_color_table {
+            palettes.push(Palette {
+                entries: read_color_table(image, size)?,
+                source: PaletteSource::GifGlobal,
+            });
+        }
+
+        let mut reader = BlockReader {
+            palettes: Some(palettes),
+            ..BlockReader::default()
+        };
+        reader.read(image, cx)?;
+
+        Ok(reader.palettes.unwrap_or_default())
     })
 }
 
-fn read_signature<R: ?Sized + BufRead + Seek>(image: &mut R) -> ImageResult {
+/// Walks the blocks after the global color table up to the trailer or the end of the image
+///
+/// `LoadOptions::default()` applies unless `Blocks::with_options` is given.
+pub fn blocks<R: ?Sized + BufRead + Seek>(image: &mut R) -> ImageResult<Blocks<'_, R>> {
+    let header = load_header(image)?;
+    skip_color_table(image, header.global_color_table)?;
+    Ok(Blocks {
+        done: false,
+        image,
+        options: LoadOptions::default(),
+        usage: Usage::default(),
+    })
+}
+
+fn read_signature<R: ?Sized + BufRead + Seek>(image: &mut R) -> ImageResult<Version> {
     let mut signature = [0u8; 6];
     image.read_exact(&mut signature)?;
     match &signature {
-        b"GIF87a" | b"GIF89a" => Ok(()),
+        b"GIF87a" => Ok(Version::Gif87a),
+        b"GIF89a" => Ok(Version::Gif89a),
         _ => Err(ImageError::InvalidSignature),
     }
 }
 
-fn read_header<R: ?Sized + BufRead + Seek>(image: &mut R) -> ImageResult<(Dimensions, Color)> {
+fn read_header<R: ?Sized + BufRead + Seek>(image: &mut R, version: Version) -> ImageResult<Header> {
     let width = image.read_u16::<LittleEndian>().map(u32::from)?;
     let height = image.read_u16::<LittleEndian>().map(u32::from)?;
 
     let bits = image.read_u8()?;
-    let table_bytes = read_table_bits(bits)?;
-    // let resolution = (bits & 0b0111_0000) >> 4;
+    let background_color_index = image.read_u8()?;
+    let pixel_aspect_ratio = image.read_u8()?;
+
+    Ok(Header {
+        background_color_index,
+        color_resolution: ((bits & 0b0111_0000) >> 4) + 1,
+        dimensions: Dimensions { width, height },
+        global_color_table: read_table_bits(bits),
+        global_color_table_sorted: (bits & 0b0000_1000) > 0,
+        pixel_aspect_ratio,
+        version,
+    })
+}
+
+fn read_color_table<R: ?Sized + BufRead + Seek>(
+    image: &mut R,
+    size: usize,
+) -> ImageResult<Vec<PaletteEntry>> {
+    let mut table = [0u8; 256 * 3];
+    let table = &mut table[..size * 3];
+    image.read_exact(table)?;
+    Ok(table
+        .chunks_exact(3)
+        .map(|it| PaletteEntry {
+            red: it[0],
+            green: it[1],
+            blue: it[2],
+            alpha: 255,
+        })
+        .collect())
+}
+
+fn skip_color_table<R: ?Sized + BufRead + Seek>(image: &mut R, size: Option<usize>) -> ImageResult {
+    if let Some(size) = size {
+        image.seek(SeekFrom::Current(size as i64 * 3))?;
+    }
+    Ok(())
+}
 
-    // 1 Background color index
-    // 1 Aspect Ratio
+impl<R: ?Sized> Blocks<'_, R> {
+    /// Applies `options` to the whole walk
+    pub fn with_options(self, options: LoadOptions) -> Self {
+        Blocks { options, ..self }
+    }
+}
+
+impl<R: ?Sized + BufRead + Seek> Iterator for Blocks<'_, R> {
+    type Item = ImageResult<Block>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
 
-    image.seek(SeekFrom::Current(table_bytes + 2))?;
+        let result = resume(
+            self.image,
+            Format::Gif,
+            &self.options,
+            &mut self.usage,
+            |image, cx| {
+                if image.fill_buf()?.is_empty() {
+                    return Ok(None);
+                }
+                cx.block()?;
+                read_block(image).map(Some)
+            },
+        );
+        self.done = !matches!(&result, Ok(Some(it)) if it.label != 0x3b);
+        result.transpose()
+    }
+}
 
-    let color = Color {
-        alpha_channel: false,
-        mode: ColorMode::Indexed,
-        resolution: 8,
+/// Reads the next block for `Blocks`
+fn read_block<R: ?Sized + BufRead + Seek>(image: &mut R) -> ImageResult<Block> {
+    let offset = image.stream_position()?;
+    let label = image.read_u8()?;
+    let extension = skip_block(image, label)
+        .map_err(|err| truncated_block(err, Format::Gif, errors::Block::Label(label), offset))?;
+    Ok(Block {
+        extension,
+        label,
+        length: image.stream_position()? - offset - 1,
+        offset,
+    })
+}
+
+/// Skips the block after `label`, and returns the extension label if any
+fn skip_block<R: ?Sized + BufRead + Seek>(image: &mut R, label: u8) -> ImageResult<Option<u8>> {
+    let extension = match label {
+        0x21 => {
+            let extension = image.read_u8()?;
+            skip_sub_blocks(image)?;
+            Some(extension)
+        }
+        0x2c => {
+            // 2 Left
+            // 2 Top
+            // 2 Width
+            // 2 Height
+            image.seek(SeekFrom::Current(8))?;
+            let bits = image.read_u8()?;
+            skip_color_table(image, read_table_bits(bits))?;
+            image.seek(SeekFrom::Current(1))?; // LZW minimum code size
+            skip_sub_blocks(image)?;
+            None
+        }
+        0x3b => None,
+        x => {
+            return Err(corrupt(
+                CorruptionKind::UnknownBlock,
+                format!("Unknown block: {:x}", x),
+            )
+            .with_block(errors::Block::Label(x))
+            .into())
+        }
     };
+    Ok(extension)
+}
+
+impl Resumable for MetaReader {
+    fn open<R: ?Sized + BufRead + Seek>(image: &mut R, cx: &mut Context) -> ImageResult<Self> {
+        let header = load_header(image)?;
+        cx.dimensions(&header.dimensions)?;
+        skip_color_table(image, header.global_color_table)?;
+        Ok(MetaReader {
+            blocks: BlockReader::default(),
+            dimensions: header.dimensions,
+        })
+    }
+
+    fn step<R: ?Sized + BufRead + Seek>(
+        &mut self,
+        image: &mut R,
+        cx: &mut Context,
+    ) -> ImageResult<bool> {
+        self.blocks.read_block(image, cx)
+    }
+
+    fn dimensions(&self) -> Option<&Dimensions> {
+        Some(&self.dimensions)
+    }
 
-    Ok((Dimensions { width, height }, color))
+    fn finish(self, result: ImageResult) -> ImageResult<ImageMeta> {
+        let color = Color {
+            alpha_channel: false,
+            mode: ColorMode::Indexed,
+            resolution: 8,
+        };
+
+        let meta = ImageMeta {
+            animation_frames: if 1 < self.blocks.frames {
+                Some(self.blocks.frames)
+            } else {
+                None
+            },
+            color,
+            color_space: None,
+            dimensions: self.dimensions,
+            format: Format::Gif,
+        };
+        complete(result, meta)
+    }
 }
 
 impl BlockReader {
-    fn read<R: ?Sized + BufRead + Seek>(&mut self, image: &mut R) -> ImageResult {
-        loop {
-            let b = image.read_u8()?;
-            match b {
-                0x21 => self.read_extension(image)?,
-                0x2c => self.read_image_data(image)?,
-                0x3b => return Ok(()),
-                x => {
-                    return Err(ImageError::CorruptImage(
-                        format!("Unknown block: {:x}", x).into(),
-                    ))
-                }
-            };
+    fn read<R: ?Sized + BufRead + Seek>(&mut self, image: &mut R, cx: &mut Context) -> ImageResult {
+        while !self.read_block(image, cx)? {}
+        Ok(())
+    }
+
+    /// Reads the next block, and returns true at the end
+    fn read_block<R: ?Sized + BufRead + Seek>(
+        &mut self,
+        image: &mut R,
+        cx: &mut Context,
+    ) -> ImageResult<bool> {
+        if cx.is_lenient() && image.fill_buf()?.is_empty() {
+            // Missing trailer
+            return Ok(true);
         }
+        cx.block()?;
+        let b = image.read_u8()?;
+        match b {
+            0x21 => self.read_extension(image, cx)?,
+            0x2c => self.read_image_data(image, cx)?,
+            0x3b if cx.is_strict() && !image.fill_buf()?.is_empty() => {
+                return Err(corrupt(
+                    CorruptionKind::UnexpectedBlock,
+                    "Trailing data after trailer",
+                )
+                .into())
+            }
+            0x3b => return Ok(true),
+            // Trailing garbage
+            _ if cx.is_lenient() => return Ok(true),
+            x => {
+                return Err(corrupt(
+                    CorruptionKind::UnknownBlock,
+                    format!("Unknown block: {:x}", x),
+                )
+                .with_block(errors::Block::Label(x))
+                .into())
+            }
+        };
+        Ok(false)
     }
 
-    fn read_extension<R: ?Sized + BufRead + Seek>(&mut self, image: &mut R) -> ImageResult {
-        match image.read_u8()? {
+    fn read_extension<R: ?Sized + BufRead + Seek>(
+        &mut self,
+        image: &mut R,
+        cx: &mut Context,
+    ) -> ImageResult {
+        let label = image.read_u8()?;
+        if cx.is_strict() {
+            verify_extension(image, label)?;
+        }
+        match label {
+            0xf9 if self.palettes.is_some() => return self.read_graphic_control(image),
+            0xfe if self.comments.is_some() => return self.read_comment(image, cx),
             0x01 | 0xf9 | 0xfe | 0xff => (),
+            _ if cx.is_lenient() => (),
             x => {
-                return Err(ImageError::CorruptImage(
-                    format!("Unknown extension: {:x}", x).into(),
-                ))
+                return Err(corrupt(
+                    CorruptionKind::UnknownBlock,
+                    format!("Unknown extension: {:x}", x),
+                )
+                .with_block(errors::Block::Label(x))
+                .into())
             }
         };
+        skip_sub_blocks(image)
+    }
+
+    fn read_comment<R: ?Sized + BufRead + Seek>(
+        &mut self,
+        image: &mut R,
+        cx: &mut Context,
+    ) -> ImageResult {
+        let mut text = vec![];
         loop {
             let size = image.read_u8()?;
             if size == 0 {
-                return Ok(());
+                break;
             }
+            cx.alloc(usize::from(size))?;
+            let mut block = [0u8; 255];
+            let block = &mut block[..usize::from(size)];
+            image.read_exact(block)?;
+            text.extend_from_slice(block);
+        }
+
+        if let Some(comments) = self.comments.as_mut() {
+            comments.push(Comment {
+                keyword: None,
+                source: CommentSource::Gif,
+                text: String::from_utf8_lossy(&text).into_owned(),
+            });
+        }
+        Ok(())
+    }
+
+    fn read_graphic_control<R: ?Sized + BufRead + Seek>(&mut self, image: &mut R) -> ImageResult {
+        let size = image.read_u8()?;
+        if size == 4 {
+            let bits = image.read_u8()?;
+            image.seek(SeekFrom::Current(2))?; // Delay time
+            let index = image.read_u8()?;
+            self.transparent_index = if bits & 1 > 0 { Some(index) } else { None };
+        } else {
             image.seek(SeekFrom::Current(i64::from(size)))?;
         }
+        skip_sub_blocks(image)
     }
 
-    fn read_image_data<R: ?Sized + BufRead + Seek>(&mut self, image: &mut R) -> ImageResult {
+    fn read_image_data<R: ?Sized + BufRead + Seek>(
+        &mut self,
+        image: &mut R,
+        cx: &mut Context,
+    ) -> ImageResult {
         // 2 Left
         // 2 Top
         // 2 Width
         // 2 Height
         image.seek(SeekFrom::Current(8))?;
 
-        let table_bytes = read_table_bits(image.read_u8()?)?;
-        image.seek(SeekFrom::Current(table_bytes + 1))?; // `+ 1` means LZW minimum code size
-
-        loop {
-            let size = image.read_u8()?;
-            if size == 0 {
-                break;
+        let bits = image.read_u8()?;
+        let transparent_index = self.transparent_index.map(usize::from);
+        match (read_table_bits(bits), self.palettes.as_mut()) {
+            (Some(size), Some(palettes)) => {
+                cx.alloc(size * size_of::<PaletteEntry>())?;
+                let mut entries = read_color_table(image, size)?;
+                if let Some(entry) = transparent_index.and_then(|it| entries.get_mut(it)) {
+                    entry.alpha = 0;
+                }
+                palettes.push(Palette {
+                    entries,
+                    source: PaletteSource::GifLocal(self.frames),
+                });
             }
-            image.seek(SeekFrom::Current(i64::from(size)))?;
+            (None, Some(palettes)) => {
+                // The frame uses the global color table, which is the first palette if any
+                let global = palettes
+                    .first()
+                    .filter(|it| it.source == PaletteSource::GifGlobal);
+                if let (Some(index), Some(global)) = (transparent_index, global) {
+                    cx.alloc(global.entries.len() * size_of::<PaletteEntry>())?;
+                    let mut entries = global.entries.clone();
+                    if let Some(entry) = entries.get_mut(index) {
+                        entry.alpha = 0;
+                    }
+                    palettes.push(Palette {
+                        entries,
+                        source: PaletteSource::GifFrame(self.frames),
+                    });
+                }
+            }
+            (size, None) => skip_color_table(image, size)?,
         }
+        self.transparent_index = None;
+        image.seek(SeekFrom::Current(1))?; // LZW minimum code size
+        skip_sub_blocks(image)?;
 
         self.frames += 1;
         Ok(())
     }
 }
 
-/// Returns the bytes to skip
-fn read_table_bits(bits: u8) -> ImageResult<i64> {
+fn skip_sub_blocks<R: ?Sized + BufRead + Seek>(image: &mut R) -> ImageResult {
+    loop {
+        let size = image.read_u8()?;
+        if size == 0 {
+            return Ok(());
+        }
+        image.seek(SeekFrom::Current(i64::from(size)))?;
+    }
+}
+
+/// Checks the size of the fixed sub-block of the extension, and rewinds to the start of it
+///
+/// Graphic control extension must be terminated right after the fixed sub-block.
+fn verify_extension<R: ?Sized + BufRead + Seek>(image: &mut R, label: u8) -> ImageResult {
+    let expected = match label {
+        0x01 => 12,
+        0xf9 => 4,
+        0xff => 11,
+        _ => return Ok(()),
+    };
+    let size = image.read_u8()?;
+    let terminated = if size == expected && label == 0xf9 {
+        image.seek(SeekFrom::Current(i64::from(size)))?;
+        let terminated = image.read_u8()? == 0;
+        image.seek(SeekFrom::Current(-i64::from(size) - 1))?;
+        terminated
+    } else {
+        true
+    };
+    image.seek(SeekFrom::Current(-1))?;
+
+    if size != expected {
+        return Err(corrupt(
+            CorruptionKind::InvalidField,
+            format!("Invalid block size: {} (expected {})", size, expected),
+        )
+        .with_block(errors::Block::Label(label))
+        .into());
+    }
+    if !terminated {
+        return Err(
+            corrupt(CorruptionKind::InvalidField, "Missing block terminator")
+                .with_block(errors::Block::Label(label))
+                .into(),
+        );
+    }
+    Ok(())
+}
+
+/// Returns the number of the color table entries
+fn read_table_bits(bits: u8) -> Option<usize> {
     let has_table = (bits & 0b1000_0000) > 0;
     let table_size = 2 << (bits & 0b0000_0111);
     if has_table {
-        Ok(i64::from(table_size) * 3)
+        Some(table_size)
     } else {
-        Ok(0)
+        None
     }
 }
+
+impl Header {
+    /// Returns the pixel aspect ratio (width / height) if specified
+    pub fn aspect_ratio(&self) -> Option<f32> {
+        if self.pixel_aspect_ratio == 0 {
+            None
+        } else {
+            Some((f32::from(self.pixel_aspect_ratio) + 15.0) / 64.0)
+        }
+    }
+}
+
+fn corrupt<T: Into<Cow<'static, str>>>(kind: CorruptionKind, message: T) -> Corruption {
+    Corruption::new(Format::Gif, kind, message)
+}
@@ -1,22 +1,142 @@
-use std::io::{BufRead, Seek};
+use alloc::borrow::Cow;
+use alloc::borrow::ToOwned;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
 
-use crate::errors::{ImageError, ImageResult};
-use crate::types::{Color, Dimensions, Format, ImageMeta};
+use crate::errors::{Corruption, CorruptionKind, ImageError, ImageResult, Unsupported};
+use crate::io::{BufRead, Seek};
+use crate::loader::limits::{limited, Context};
+use crate::types::{
+    Color, ColorMode, Comment, CommentSource, Dimensions, Format, ImageMeta, LoadOptions,
+};
+
+// See: https://radsite.lbl.gov/radiance/refer/filefmts.pdf
 
 const SIGNATURE: [u8; 11] = [
     0x23, 0x3f, 0x52, 0x41, 0x44, 0x49, 0x41, 0x4e, 0x43, 0x45, 0x0a,
 ];
 
+/// Lines up to this length are read without allocation
+const LINE_BUFFER_SIZE: usize = 256;
+
+/// Information header and resolution string of Radiance HDR
+#[derive(Clone, Debug, PartialEq)]
+pub struct Header {
+    /// Texts of `#` lines
+    pub comments: Vec<String>,
+    /// Width is the length of X axis, and height is the length of Y axis
+    pub dimensions: Dimensions,
+    /// Product of all EXPOSURE values
+    pub exposure: Option<f64>,
+    pub gamma: Option<f64>,
+    pub orientation: Orientation,
+    /// Product of all PIXASPECT values
+    pub pixel_aspect: Option<f64>,
+    pub pixel_format: PixelFormat,
+    /// CIE (x, y) of red, green, blue and white
+    pub primaries: Option<[f64; 8]>,
+    pub software: Option<String>,
+}
+
+/// Order of pixels given by the resolution string
+#[derive(Clone, Copy, Debug, Eq, PartialEq, strum::Display)]
+pub enum Orientation {
+    /// `-Y N +X M` (Standard: left to right, top to bottom)
+    NegYPosX,
+    /// `-Y N -X M`
+    NegYNegX,
+    /// `+Y N -X M`
+    PosYNegX,
+    /// `+Y N +X M`
+    PosYPosX,
+    /// `+X M -Y N`
+    PosXNegY,
+    /// `-X M -Y N`
+    NegXNegY,
+    /// `-X M +Y N`
+    NegXPosY,
+    /// `+X M +Y N`
+    PosXPosY,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, strum::Display)]
+pub enum PixelFormat {
+    /// `32-bit_rle_rgbe`
+    Rgbe,
+    /// `32-bit_rle_xyze`
+    Xyze,
+}
+
+/// Line buffer on the stack, moved to the heap if the line is longer
+struct LineBuffer {
+    heap: Vec<u8>,
+    length: usize,
+    stack: [u8; LINE_BUFFER_SIZE],
+}
+
 pub fn load<R: ?Sized + BufRead + Seek>(image: &mut R) -> ImageResult<ImageMeta> {
-    read_signature(image)?;
+    load_with_options(image, &LoadOptions::default())
+}
+
+pub fn load_with_options<R: ?Sized + BufRead + Seek>(
+    image: &mut R,
+    options: &LoadOptions,
+) -> ImageResult<ImageMeta> {
+    limited(image, Format::Hdr, options, |image, cx| {
+        read_signature(image)?;
+        // Skip the texts to avoid allocation, and the variables not needed
+        let header = read_header(image, false, cx)?;
+
+        let color = Color {
+            mode: ColorMode::Rgb,
+            alpha_channel: false,
+            resolution: 32,
+        };
+
+        Ok(ImageMeta {
+            animation_frames: None,
+            color,
+            color_space: None,
+            dimensions: header.dimensions,
+            format: Format::Hdr,
+        })
+    })
+}
+
+/// Returns the texts of `#` lines in the information header
+pub fn load_comments<R: ?Sized + BufRead + Seek>(image: &mut R) -> ImageResult<Vec<Comment>> {
+    load_comments_with_options(image, &LoadOptions::default())
+}
+
+pub fn load_comments_with_options<R: ?Sized + BufRead + Seek>(
+    image: &mut R,
+    options: &LoadOptions,
+) -> ImageResult<Vec<Comment>> {
+    let result = load_header_with_options(image, options)?
+        .comments
+        .into_iter()
+        .map(|text| Comment {
+            keyword: None,
+            source: CommentSource::Hdr,
+            text,
+        })
+        .collect();
+    Ok(result)
+}
 
-    let (dimensions, color) = read_header(image)?;
+pub fn load_header<R: ?Sized + BufRead + Seek>(image: &mut R) -> ImageResult<Header> {
+    load_header_with_options(image, &LoadOptions::default())
+}
 
-    Ok(ImageMeta {
-        animation_frames: None,
-        color,
-        dimensions,
-        format: Format::Hdr,
+pub fn load_header_with_options<R: ?Sized + BufRead + Seek>(
+    image: &mut R,
+    options: &LoadOptions,
+) -> ImageResult<Header> {
+    limited(image, Format::Hdr, options, |image, cx| {
+        read_signature(image)?;
+        read_header(image, true, cx)
     })
 }
 
@@ -29,66 +149,257 @@ fn read_signature<R: ?Sized + BufRead + Seek>(image: &mut R) -> ImageResult {
     Ok(())
 }
 
-fn read_header<R: ?Sized + BufRead + Seek>(image: &mut R) -> ImageResult<(Dimensions, Color)> {
-    use crate::types::ColorMode::*;
+/// Reads the information header and the resolution string
+///
+/// `full` enables the comments, software and the other variables than FORMAT, which are not
+/// validated otherwise.
+fn read_header<R: ?Sized + BufRead + Seek>(
+    image: &mut R,
+    full: bool,
+    cx: &mut Context,
+) -> ImageResult<Header> {
+    let mut comments = vec![];
+    let mut exposure = None;
+    let mut gamma = None;
+    let mut pixel_aspect = None;
+    let mut pixel_format = PixelFormat::Rgbe;
+    let mut primaries = None;
+    let mut software = None;
 
-    let color = Color {
-        mode: Rgb,
-        alpha_channel: false,
-        resolution: 32,
-    };
-    while let Some(Ok(line)) = image.lines().next() {
-        // Skip empty lines and comments
-        if line.starts_with('#') || line.is_empty() {
+    let mut buffer = LineBuffer::new();
+    while let Some(line) = buffer.read_line(image, cx)? {
+        cx.block()?;
+        if let Some(comment) = line.strip_prefix('#') {
+            if full {
+                cx.alloc(comment.len())?;
+                comments.push(comment.trim().to_owned());
+            }
+            continue;
+        }
+        if line.is_empty() {
             continue;
         }
 
         // Try to parse the line as a key-value pair
-        if let Some((key, value)) = line.split_once("=") {
+        if let Some((key, value)) = line.split_once('=') {
+            let value = value.trim();
             match key {
-                "FORMAT" => match value {
-                    "32-bit_rle_rgbe" => {}
-                    "32-bit_rle_xyze" => {}
-                    _ => {
-                        return Err(ImageError::CorruptImage(
-                            format!("Unsupported format: {}", value).into(),
-                        ));
+                "EXPOSURE" if full => {
+                    exposure = Some(exposure.unwrap_or(1.0) * parse_number(key, value)?);
+                }
+                "FORMAT" => {
+                    pixel_format = match value {
+                        "32-bit_rle_rgbe" => PixelFormat::Rgbe,
+                        "32-bit_rle_xyze" => PixelFormat::Xyze,
+                        _ => {
+                            return Err(corrupt(
+                                CorruptionKind::InvalidField,
+                                format!("Unsupported format: {}", value),
+                            )
+                            .into());
+                        }
                     }
-                },
-                "PRIMARIES" => {}
+                }
+                "GAMMA" if full => gamma = Some(parse_number(key, value)?),
+                "PIXASPECT" if full => {
+                    pixel_aspect = Some(pixel_aspect.unwrap_or(1.0) * parse_number(key, value)?);
+                }
+                "PRIMARIES" if full => primaries = Some(parse_primaries(value)?),
+                "SOFTWARE" if full => {
+                    cx.alloc(value.len())?;
+                    software = Some(value.to_owned());
+                }
                 _ => {}
             }
         }
         // Else, we have reached the resolution line
         else {
-            let mut iter = line.split_whitespace();
-            let c1_tag = iter
-                .next()
-                .ok_or(ImageError::CorruptImage("Error parsing dimension".into()))?;
-            let c1_str = iter
-                .next()
-                .ok_or(ImageError::CorruptImage("Error parsing dimension".into()))?;
-            let c2_tag = iter
-                .next()
-                .ok_or(ImageError::CorruptImage("Error parsing dimension".into()))?;
-            let c2_str = iter
-                .next()
-                .ok_or(ImageError::CorruptImage("Error parsing dimension".into()))?;
-            match (c1_tag, c2_tag) {
-                ("-Y", "+X") => {
-                    // Common orientation (left-right, top-down)
-                    // c1_str is height, c2_str is width
-                    let height = c1_str.parse::<u32>().map_err(|err| {
-                        ImageError::CorruptImage(format!("Error parsing height: {err}").into())
-                    })?;
-                    let width = c2_str.parse::<u32>().map_err(|err| {
-                        ImageError::CorruptImage(format!("Error parsing width: {err}").into())
-                    })?;
-                    return Ok((Dimensions { width, height }, color));
-                }
-                _ => return Err(ImageError::Unsupported),
+            let (orientation, dimensions) = parse_resolution(line)?;
+            cx.dimensions(&dimensions)?;
+            return Ok(Header {
+                comments,
+                dimensions,
+                exposure,
+                gamma,
+                orientation,
+                pixel_aspect,
+                pixel_format,
+                primaries,
+                software,
+            });
+        }
+    }
+    Err(corrupt(CorruptionKind::Truncated, "Resolution string not found").into())
+}
+
+fn parse_resolution(line: &str) -> ImageResult<(Orientation, Dimensions)> {
+    use Orientation::*;
+
+    let mut iter = line.split_whitespace();
+    let mut next = || {
+        iter.next()
+            .ok_or_else(|| corrupt(CorruptionKind::InvalidField, "Error parsing dimension"))
+    };
+    let c1_tag = next()?;
+    let c1 = parse_length(next()?)?;
+    let c2_tag = next()?;
+    let c2 = parse_length(next()?)?;
+
+    // The first axis is the scanline direction
+    let (orientation, height, width) = match (c1_tag, c2_tag) {
+        ("-Y", "+X") => (NegYPosX, c1, c2),
+        ("-Y", "-X") => (NegYNegX, c1, c2),
+        ("+Y", "-X") => (PosYNegX, c1, c2),
+        ("+Y", "+X") => (PosYPosX, c1, c2),
+        ("+X", "-Y") => (PosXNegY, c2, c1),
+        ("-X", "-Y") => (NegXNegY, c2, c1),
+        ("-X", "+Y") => (NegXPosY, c2, c1),
+        ("+X", "+Y") => (PosXPosY, c2, c1),
+        _ => {
+            return Err(corrupt(
+                CorruptionKind::InvalidField,
+                format!("Invalid resolution string: {}", line),
+            )
+            .into())
+        }
+    };
+
+    Ok((orientation, Dimensions { width, height }))
+}
+
+fn parse_length(value: &str) -> ImageResult<u32> {
+    value.parse::<u32>().map_err(|err| {
+        corrupt(
+            CorruptionKind::InvalidField,
+            format!("Error parsing dimension: {err}"),
+        )
+        .into()
+    })
+}
+
+fn parse_number(key: &str, value: &str) -> ImageResult<f64> {
+    value.parse::<f64>().map_err(|err| {
+        corrupt(
+            CorruptionKind::InvalidField,
+            format!("Error parsing {key}: {err}"),
+        )
+        .into()
+    })
+}
+
+fn parse_primaries(value: &str) -> ImageResult<[f64; 8]> {
+    let mut result = [0.0; 8];
+    let mut iter = value.split_whitespace();
+    for it in result.iter_mut() {
+        let value = iter
+            .next()
+            .ok_or_else(|| corrupt(CorruptionKind::InvalidField, "Error parsing PRIMARIES"))?;
+        *it = parse_number("PRIMARIES", value)?;
+    }
+    Ok(result)
+}
+
+impl LineBuffer {
+    fn new() -> Self {
+        LineBuffer {
+            heap: vec![],
+            length: 0,
+            stack: [0u8; LINE_BUFFER_SIZE],
+        }
+    }
+
+    /// Returns the next line without the line terminator, or None at the end
+    fn read_line<R: ?Sized + BufRead>(
+        &mut self,
+        image: &mut R,
+        cx: &Context,
+    ) -> ImageResult<Option<&str>> {
+        self.heap.clear();
+        self.length = 0;
+
+        let mut empty = true;
+        loop {
+            let available = image.fill_buf()?;
+            if available.is_empty() {
+                break;
             }
+            empty = false;
+            let (data, used, end) = match available.iter().position(|it| *it == b'\n') {
+                Some(at) => (&available[..at], at + 1, true),
+                None => (available, available.len(), false),
+            };
+            cx.line(self.len() + data.len())?;
+            self.push(data);
+            image.consume(used);
+            if end {
+                break;
+            }
+        }
+        if empty {
+            return Ok(None);
+        }
+
+        let line = if self.heap.is_empty() {
+            &self.stack[..self.length]
+        } else {
+            &self.heap[..]
+        };
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        // Not a text header of Radiance
+        core::str::from_utf8(line).map(Some).map_err(|_| {
+            ImageError::Unsupported(Unsupported {
+                signature: SIGNATURE.to_vec(),
+                tried: vec![Format::Hdr],
+            })
+        })
+    }
+
+    fn len(&self) -> usize {
+        if self.heap.is_empty() {
+            self.length
+        } else {
+            self.heap.len()
+        }
+    }
+
+    fn push(&mut self, data: &[u8]) {
+        if self.heap.is_empty() && self.length + data.len() <= LINE_BUFFER_SIZE {
+            self.stack[self.length..self.length + data.len()].copy_from_slice(data);
+            self.length += data.len();
+            return;
+        }
+        if self.heap.is_empty() {
+            self.heap.extend_from_slice(&self.stack[..self.length]);
+        }
+        self.heap.extend_from_slice(data);
+    }
+}
+
+impl Orientation {
+    /// Returns the resolution string without lengths (e.g. `-Y +X`)
+    pub fn as_str(&self) -> &'static str {
+        use Orientation::*;
+
+        match self {
+            NegYPosX => "-Y +X",
+            NegYNegX => "-Y -X",
+            PosYNegX => "+Y -X",
+            PosYPosX => "+Y +X",
+            PosXNegY => "+X -Y",
+            NegXNegY => "-X -Y",
+            NegXPosY => "-X +Y",
+            PosXPosY => "+X +Y",
         }
     }
-    Err(ImageError::Unsupported)
+
+    /// Returns true if the scanlines run vertically
+    pub fn is_transposed(&self) -> bool {
+        use Orientation::*;
+
+        matches!(self, PosXNegY | NegXNegY | NegXPosY | PosXPosY)
+    }
+}
+
+fn corrupt<T: Into<Cow<'static, str>>>(kind: CorruptionKind, message: T) -> Corruption {
+    Corruption::new(Format::Hdr, kind, message)
 }
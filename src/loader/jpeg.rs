@@ -1,26 +1,79 @@
-use std::io::{BufRead, Cursor, Seek, SeekFrom};
+use alloc::borrow::Cow;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
 
-use byteorder::{BigEndian, ReadBytesExt};
+use byteorder::BigEndian;
 
-use crate::errors::{ImageError, ImageResult};
-use crate::types::{Color, ColorMode, Dimensions, Format, ImageMeta};
+use crate::errors::{Block, Corruption, CorruptionKind, ImageError, ImageResult};
+use crate::io::{BufRead, Cursor, Read, ReadExt, Seek, SeekFrom};
+use crate::loader::limits::{limited, resume, truncated_block, truncation, Context, Usage};
+use crate::loader::resumable::{run, Resumable};
+use crate::loader::tiff::Tiff;
+use crate::loader::{read_data, skip_data};
+use crate::types::{
+    Color, ColorMode, Comment, CommentSource, Dimensions, Format, ImageMeta, LoadOptions,
+    MultiPicture, MultiPictureImage, MultiPictureKind, Thumbnail, ThumbnailEncoding,
+    ThumbnailSource,
+};
 
 const MARKER: u8 = 0xff;
 const SOI: u8 = 0xd8;
+const EOI: u8 = 0xd9;
+const SOS: u8 = 0xda;
+const APP0: u8 = 0xe0;
+const APP1: u8 = 0xe1;
+const APP2: u8 = 0xe2;
+const APP13: u8 = 0xed;
+const COM: u8 = 0xfe;
+
+/// Segment or stand-alone marker found by `segments`
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Segment {
+    /// Segment data excluding the length field if requested by `Segments::with_data`
+    pub data: Option<Vec<u8>>,
+    /// Length of the segment data excluding the length field (0 for stand-alone markers)
+    pub length: u16,
+    pub marker: u8,
+    /// Offset of the marker in the image
+    pub offset: u64,
+}
+
+/// Iterator over the segments up to EOI, see `segments`
+pub struct Segments<'a, R: ?Sized> {
+    done: bool,
+    image: &'a mut R,
+    options: LoadOptions,
+    usage: Usage,
+    with_data: bool,
+}
+
+/// State of `check_structure`
+#[derive(Default)]
+struct Validator {
+    findings: Vec<Corruption>,
+    frame: bool,
+    /// Marker of the segment being walked
+    marker: Option<u8>,
+}
+
+/// State of `load` between the segments
+#[derive(Clone)]
+pub(crate) struct MetaReader {
+    dimensions: Option<Dimensions>,
+}
 
 pub fn load<R: ?Sized + BufRead + Seek>(image: &mut R) -> ImageResult<ImageMeta> {
-    read_signature(image)?;
-    let dimensions = read_sof(image)?;
-    let color = Color {
-        alpha_channel: false,
-        mode: ColorMode::Rgb,
-        resolution: 8,
-    };
-    Ok(ImageMeta {
-        animation_frames: None,
-        color,
-        dimensions,
-        format: Format::Jpeg,
+    load_with_options(image, &LoadOptions::default())
+}
+
+pub fn load_with_options<R: ?Sized + BufRead + Seek>(
+    image: &mut R,
+    options: &LoadOptions,
+) -> ImageResult<ImageMeta> {
+    limited(image, Format::Jpeg, options, |image, cx| {
+        run::<MetaReader, _>(image, cx)
     })
 }
 
@@ -33,28 +86,654 @@ fn read_signature<R: ?Sized + BufRead + Seek>(image: &mut R) -> ImageResult {
     Ok(())
 }
 
-fn read_sof<R: ?Sized + BufRead + Seek>(image: &mut R) -> ImageResult<Dimensions> {
+fn read_sof<R: ?Sized + BufRead + Seek>(
+    image: &mut R,
+    cx: &mut Context,
+) -> ImageResult<Dimensions> {
     loop {
-        if let (_, Some(data)) = read_segment(image, is_sof)? {
-            let mut data = Cursor::new(data);
-            data.seek(SeekFrom::Current(1))?;
-            let height = data.read_u16::<BigEndian>().map(u32::from)?;
-            let width = data.read_u16::<BigEndian>().map(u32::from)?;
-            return Ok(Dimensions { width, height });
+        if let Some(dimensions) = read_header_segment(image, cx)? {
+            return Ok(dimensions);
+        }
+    }
+}
+
+/// Reads the next segment before the frame, and returns the dimensions if it is SOF
+fn read_header_segment<R: ?Sized + BufRead + Seek>(
+    image: &mut R,
+    cx: &mut Context,
+) -> ImageResult<Option<Dimensions>> {
+    cx.block()?;
+    let marker = read_marker(image)?;
+    if cx.is_strict() && !is_header_marker(marker) {
+        return Err(corrupt(
+            CorruptionKind::UnexpectedBlock,
+            "Unexpected marker before SOF",
+        )
+        .with_block(Block::Marker(marker))
+        .into());
+    }
+    let length = read_length(image, marker)?;
+    if !is_sof(marker) {
+        image.seek(SeekFrom::Current(i64::from(length)))?;
+        return Ok(None);
+    }
+    if length < 5 {
+        return Err(corrupt(
+            CorruptionKind::Truncated,
+            format!("Too short SOF: {}", length),
+        )
+        .with_block(Block::Marker(marker))
+        .into());
+    }
+    image.seek(SeekFrom::Current(1))?; // Sample precision
+    let height = image.read_u16::<BigEndian>().map(u32::from)?;
+    let width = image.read_u16::<BigEndian>().map(u32::from)?;
+    Ok(Some(Dimensions { width, height }))
+}
+
+/// Walks the whole image, and returns the findings instead of failing on the first
+///
+/// Unlike `load`, which stops at the first SOF, the segments, the entropy-coded data of the
+/// scans, restart markers and EOI are checked. Missing EOI, truncated scans, garbage after EOI,
+/// bad segment lengths and misplaced markers are reported. Errors are returned for non-JPEG images
+/// and I/O errors.
+pub fn check_structure<R: ?Sized + BufRead + Seek>(image: &mut R) -> ImageResult<Vec<Corruption>> {
+    check_structure_with_options(image, &LoadOptions::default())
+}
+
+pub fn check_structure_with_options<R: ?Sized + BufRead + Seek>(
+    image: &mut R,
+    options: &LoadOptions,
+) -> ImageResult<Vec<Corruption>> {
+    limited(image, Format::Jpeg, options, |image, cx| {
+        read_signature(image)?;
+
+        let mut validator = Validator::default();
+        if let Err(err) = validator.walk(image, cx) {
+            let mut corruption = truncation(err, Format::Jpeg)?;
+            if corruption.block.is_none() {
+                corruption.block = validator.marker.map(Block::Marker);
+            }
+            validator
+                .findings
+                .push(corruption.with_offset(image.stream_position()?));
+        }
+        Ok(validator.findings)
+    })
+}
+
+/// Walks the segments after SOI up to EOI or the end of the image
+///
+/// The entropy-coded data following SOS is skipped, including the restart markers in it.
+/// `LoadOptions::default()` applies unless `Segments::with_options` is given.
+pub fn segments<R: ?Sized + BufRead + Seek>(image: &mut R) -> ImageResult<Segments<'_, R>> {
+    read_signature(image)?;
+    Ok(Segments {
+        done: false,
+        image,
+        options: LoadOptions::default(),
+        usage: Usage::default(),
+        with_data: false,
+    })
+}
+
+/// Finds the embedded thumbnails (JFIF, JFXX, EXIF and Photoshop) in the segments before the first scan
+pub fn load_thumbnails<R: ?Sized + BufRead + Seek>(image: &mut R) -> ImageResult<Vec<Thumbnail>> {
+    load_thumbnails_with_options(image, &LoadOptions::default())
+}
+
+pub fn load_thumbnails_with_options<R: ?Sized + BufRead + Seek>(
+    image: &mut R,
+    options: &LoadOptions,
+) -> ImageResult<Vec<Thumbnail>> {
+    limited(image, Format::Jpeg, options, |image, cx| {
+        read_thumbnails(image, cx)
+    })
+}
+
+fn read_thumbnails<R: ?Sized + BufRead + Seek>(
+    image: &mut R,
+    cx: &mut Context,
+) -> ImageResult<Vec<Thumbnail>> {
+    read_signature(image)?;
+
+    let mut result = vec![];
+    for (marker, offset, data) in
+        read_header_segments(image, cx, |it| matches!(it, APP0 | APP1 | APP13))?
+    {
+        match marker {
+            APP0 => result.extend(read_jfif_thumbnail(&data, offset)),
+            APP1 => result.extend(read_exif_thumbnail(&data, offset)?),
+            APP13 => result.extend(read_photoshop_thumbnails(&data, offset)?),
+            _ => unreachable!(),
+        }
+    }
+
+    for thumbnail in &mut result {
+        if thumbnail.dimensions.is_none() && thumbnail.encoding == ThumbnailEncoding::Jpeg {
+            image.seek(SeekFrom::Start(thumbnail.range.start))?;
+            thumbnail.dimensions = read_signature(image).and_then(|_| read_sof(image, cx)).ok();
         }
     }
+
+    Ok(result)
+}
+
+/// Returns the texts of COM segments before the first scan
+pub fn load_comments<R: ?Sized + BufRead + Seek>(image: &mut R) -> ImageResult<Vec<Comment>> {
+    load_comments_with_options(image, &LoadOptions::default())
 }
 
-fn read_segment<R: ?Sized + BufRead + Seek, F>(
+pub fn load_comments_with_options<R: ?Sized + BufRead + Seek>(
     image: &mut R,
+    options: &LoadOptions,
+) -> ImageResult<Vec<Comment>> {
+    limited(image, Format::Jpeg, options, |image, cx| {
+        read_signature(image)?;
+
+        let result = read_header_segments(image, cx, |it| it == COM)?
+            .into_iter()
+            .map(|(_, _, data)| Comment {
+                keyword: None,
+                source: CommentSource::Jpeg,
+                text: String::from_utf8_lossy(&data).into_owned(),
+            })
+            .collect();
+        Ok(result)
+    })
+}
+
+/// Lists the images of Multi-Picture Format (CIPA DC-007) and detects HDR gain maps
+pub fn load_multi_picture<R: ?Sized + BufRead + Seek>(image: &mut R) -> ImageResult<MultiPicture> {
+    load_multi_picture_with_options(image, &LoadOptions::default())
+}
+
+pub fn load_multi_picture_with_options<R: ?Sized + BufRead + Seek>(
+    image: &mut R,
+    options: &LoadOptions,
+) -> ImageResult<MultiPicture> {
+    limited(image, Format::Jpeg, options, |image, cx| {
+        read_multi_picture(image, cx)
+    })
+}
+
+fn read_multi_picture<R: ?Sized + BufRead + Seek>(
+    image: &mut R,
+    cx: &mut Context,
+) -> ImageResult<MultiPicture> {
+    let start = image.stream_position()?;
+    read_signature(image)?;
+
+    let mut result = MultiPicture::default();
+    for (marker, offset, data) in read_header_segments(image, cx, |it| matches!(it, APP1 | APP2))? {
+        match marker {
+            APP1 => result.gain_map |= is_gain_map_xmp(&data),
+            APP2 => result.images.extend(read_mp_index(&data, offset, start)?),
+            _ => unreachable!(),
+        }
+    }
+
+    // The gain map image carries its own XMP (Ultra HDR and Adobe gain map)
+    for mp_image in result.images.iter_mut().skip(1) {
+        image.seek(SeekFrom::Start(mp_image.range.start))?;
+        if read_signature(image).is_err() {
+            continue;
+        }
+        if let Ok(segments) = read_header_segments(image, cx, |it| it == APP1) {
+            mp_image.gain_map = segments.iter().any(|(_, _, data)| is_gain_map_xmp(data));
+        }
+    }
+
+    Ok(result)
+}
+
+fn read_mp_index(data: &[u8], offset: u64, start: u64) -> ImageResult<Vec<MultiPictureImage>> {
+    const HEADER: &[u8] = b"MPF\0";
+
+    let mut result = vec![];
+    if !data.starts_with(HEADER) {
+        return Ok(result);
+    }
+    let tiff = Tiff::new(&data[HEADER.len()..])?;
+    // Offsets are relative to the endian field, except for the first image
+    let base = offset + HEADER.len() as u64;
+
+    let (entries, _) = tiff.read_ifd(tiff.first_ifd()?)?;
+    for entry in &entries {
+        // MP Entry
+        if entry.tag != 0xb002 {
+            continue;
+        }
+        for bytes in tiff.value_bytes(entry)?.chunks_exact(16) {
+            let mut bytes = Cursor::new(bytes);
+            let attribute = tiff.read_u32(&mut bytes)?;
+            let size = u64::from(tiff.read_u32(&mut bytes)?);
+            let data_offset = u64::from(tiff.read_u32(&mut bytes)?);
+            let image_start = if data_offset == 0 {
+                start
+            } else {
+                base + data_offset
+            };
+            result.push(MultiPictureImage {
+                gain_map: false,
+                kind: MultiPictureKind::from(attribute & 0x00ff_ffff),
+                range: image_start..image_start + size,
+                representative: attribute & 0x2000_0000 != 0,
+            });
+        }
+    }
+
+    Ok(result)
+}
+
+fn is_gain_map_xmp(data: &[u8]) -> bool {
+    const HEADER: &[u8] = b"http://ns.adobe.com/xap/1.0/\0";
+    const NAMESPACE: &[u8] = b"http://ns.adobe.com/hdr-gain-map/1.0/";
+
+    data.starts_with(HEADER)
+        && data[HEADER.len()..]
+            .windows(NAMESPACE.len())
+            .any(|it| it == NAMESPACE)
+}
+
+fn read_jfif_thumbnail(data: &[u8], offset: u64) -> Option<Thumbnail> {
+    let (source, encoding, width, height, start) = match data {
+        // 2 Version, 1 Units, 2 Xdensity, 2 Ydensity
+        [b'J', b'F', b'I', b'F', 0, _, _, _, _, _, _, _, w, h, ..] => {
+            (ThumbnailSource::Jfif, ThumbnailEncoding::Rgb, *w, *h, 14)
+        }
+        [b'J', b'F', b'X', b'X', 0, 0x10, ..] => {
+            (ThumbnailSource::Jfxx, ThumbnailEncoding::Jpeg, 0, 0, 6)
+        }
+        [b'J', b'F', b'X', b'X', 0, 0x11, w, h, ..] => {
+            (ThumbnailSource::Jfxx, ThumbnailEncoding::Indexed, *w, *h, 8)
+        }
+        [b'J', b'F', b'X', b'X', 0, 0x13, w, h, ..] => {
+            (ThumbnailSource::Jfxx, ThumbnailEncoding::Rgb, *w, *h, 8)
+        }
+        _ => return None,
+    };
+
+    let dimensions = if encoding == ThumbnailEncoding::Jpeg {
+        None
+    } else if width == 0 || height == 0 {
+        return None;
+    } else {
+        Some(Dimensions {
+            width: u32::from(width),
+            height: u32::from(height),
+        })
+    };
+
+    Some(Thumbnail {
+        dimensions,
+        encoding,
+        range: offset + start..offset + data.len() as u64,
+        source,
+    })
+}
+
+fn read_exif_thumbnail(data: &[u8], offset: u64) -> ImageResult<Option<Thumbnail>> {
+    const HEADER: &[u8] = b"Exif\0\0";
+
+    if !data.starts_with(HEADER) {
+        return Ok(None);
+    }
+    let tiff = Tiff::new(&data[HEADER.len()..])?;
+
+    let (_, ifd1) = tiff.read_ifd(tiff.first_ifd()?)?;
+    if ifd1 == 0 {
+        return Ok(None);
+    }
+
+    let (entries, _) = tiff.read_ifd(ifd1)?;
+    let mut start = None;
+    let mut length = None;
+    for entry in &entries {
+        match entry.tag {
+            0x0201 => start = tiff.value_u32(entry),
+            0x0202 => length = tiff.value_u32(entry),
+            _ => (),
+        }
+    }
+
+    if let (Some(start), Some(length)) = (start, length) {
+        let start = offset + HEADER.len() as u64 + u64::from(start);
+        return Ok(Some(Thumbnail {
+            dimensions: None,
+            encoding: ThumbnailEncoding::Jpeg,
+            range: start..start + u64::from(length),
+            source: ThumbnailSource::Exif,
+        }));
+    }
+
+    Ok(None)
+}
+
+fn read_photoshop_thumbnails(data: &[u8], offset: u64) -> ImageResult<Vec<Thumbnail>> {
+    const HEADER: &[u8] = b"Photoshop 3.0\0";
+
+    let mut result = vec![];
+    if !data.starts_with(HEADER) {
+        return Ok(result);
+    }
+
+    let mut data = Cursor::new(&data[HEADER.len()..]);
+    let base = offset + HEADER.len() as u64;
+    let length = data.get_ref().len() as u64;
+    while data.position() + 12 <= length {
+        let mut signature = [0u8; 4];
+        data.read_exact(&mut signature)?;
+        if signature != *b"8BIM" {
+            break;
+        }
+        let id = data.read_u16::<BigEndian>()?;
+        // Pascal string padded to make the size even
+        let name_length = data.read_u8()?;
+        data.seek(SeekFrom::Current(i64::from(name_length | 1)))?;
+        let size = data.read_u32::<BigEndian>()?;
+        let start = data.position();
+
+        // 0x0409 for Photoshop 4.0, 0x040c for later
+        if (id == 0x0409 || id == 0x040c) && 28 <= size {
+            let format = data.read_u32::<BigEndian>()?;
+            let width = data.read_u32::<BigEndian>()?;
+            let height = data.read_u32::<BigEndian>()?;
+            // 4 Width bytes, 4 Total size, 4 Compressed size, 2 Bits per pixel, 2 Planes
+            let thumbnail_start = base + start + 28;
+            // 1 means JPEG
+            if format == 1 {
+                result.push(Thumbnail {
+                    dimensions: Some(Dimensions { width, height }),
+                    encoding: ThumbnailEncoding::Jpeg,
+                    range: thumbnail_start..base + start + u64::from(size),
+                    source: ThumbnailSource::Photoshop,
+                });
+            }
+        }
+
+        data.set_position(start + u64::from(size) + u64::from(size & 1));
+    }
+
+    Ok(result)
+}
+
+/// Reads the segments before the first scan, returns the target segments with the offsets of their data
+fn read_header_segments<R: ?Sized + BufRead + Seek, F>(
+    image: &mut R,
+    cx: &mut Context,
     target_marker: F,
-) -> ImageResult<(u8, Option<Vec<u8>>)>
+) -> ImageResult<Vec<(u8, u64, Vec<u8>)>>
 where
     F: Fn(u8) -> bool,
 {
+    let mut result = vec![];
+    loop {
+        cx.block()?;
+        let marker = read_marker(image)?;
+        if marker == SOS || marker == EOI {
+            return Ok(result);
+        }
+        let length = read_length(image, marker)?;
+        let offset = image.stream_position()?;
+        if target_marker(marker) {
+            cx.alloc(usize::from(length))?;
+            let data = read_data(image, u64::from(length))?;
+            result.push((marker, offset, data));
+        } else {
+            image.seek(SeekFrom::Current(i64::from(length)))?;
+        }
+    }
+}
+
+impl Resumable for MetaReader {
+    fn open<R: ?Sized + BufRead + Seek>(image: &mut R, _: &mut Context) -> ImageResult<Self> {
+        read_signature(image)?;
+        Ok(MetaReader { dimensions: None })
+    }
+
+    /// Reads up to SOF
+    fn step<R: ?Sized + BufRead + Seek>(
+        &mut self,
+        image: &mut R,
+        cx: &mut Context,
+    ) -> ImageResult<bool> {
+        let Some(dimensions) = read_header_segment(image, cx)? else {
+            return Ok(false);
+        };
+        cx.dimensions(&dimensions)?;
+        self.dimensions = Some(dimensions);
+        Ok(true)
+    }
+
+    fn dimensions(&self) -> Option<&Dimensions> {
+        self.dimensions.as_ref()
+    }
+
+    fn finish(self, result: ImageResult) -> ImageResult<ImageMeta> {
+        result?;
+        let dimensions = self
+            .dimensions
+            .ok_or_else(|| corrupt(CorruptionKind::Truncated, "SOF not found"))?;
+        let color = Color {
+            alpha_channel: false,
+            mode: ColorMode::Rgb,
+            resolution: 8,
+        };
+        Ok(ImageMeta {
+            animation_frames: None,
+            color,
+            color_space: None,
+            dimensions,
+            format: Format::Jpeg,
+        })
+    }
+}
+
+impl<R: ?Sized> Segments<'_, R> {
+    /// Reads the data of each segment
+    pub fn with_data(self) -> Self {
+        Segments {
+            with_data: true,
+            ..self
+        }
+    }
+
+    /// Applies `options` to the whole walk
+    pub fn with_options(self, options: LoadOptions) -> Self {
+        Segments { options, ..self }
+    }
+}
+
+impl<R: ?Sized + BufRead + Seek> Iterator for Segments<'_, R> {
+    type Item = ImageResult<Segment>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        let with_data = self.with_data;
+        let result = resume(
+            self.image,
+            Format::Jpeg,
+            &self.options,
+            &mut self.usage,
+            |image, cx| {
+                if image.fill_buf()?.is_empty() {
+                    return Ok(None);
+                }
+                cx.block()?;
+                read_segment(image, cx, with_data).map(Some)
+            },
+        );
+        self.done = !matches!(&result, Ok(Some(it)) if it.marker != EOI);
+        result.transpose()
+    }
+}
+
+/// Reads the next segment for `Segments`
+fn read_segment<R: ?Sized + BufRead + Seek>(
+    image: &mut R,
+    cx: &mut Context,
+    with_data: bool,
+) -> ImageResult<Segment> {
+    let offset = image.stream_position()?;
+    let marker = read_marker(image)?;
+    if is_stand_alone(marker) {
+        return Ok(Segment {
+            data: None,
+            length: 0,
+            marker,
+            offset,
+        });
+    }
+
+    let length = read_length(image, marker)?;
+    let data = if with_data {
+        cx.alloc(usize::from(length))?;
+        read_data(image, u64::from(length)).map(Some)
+    } else {
+        skip_data(image, u64::from(length)).map(|_| None)
+    };
+    let data =
+        data.map_err(|err| truncated_block(err, Format::Jpeg, Block::Marker(marker), offset))?;
+    if marker == SOS {
+        skip_entropy_coded_data(image)?;
+    }
+    Ok(Segment {
+        data,
+        length,
+        marker,
+        offset,
+    })
+}
+
+impl Validator {
+    /// Walks the segments up to EOI
+    fn walk<R: ?Sized + BufRead + Seek>(&mut self, image: &mut R, cx: &mut Context) -> ImageResult {
+        loop {
+            if image.fill_buf()?.is_empty() {
+                self.marker = None;
+                return Err(corrupt(CorruptionKind::Truncated, "EOI not found").into());
+            }
+            cx.block()?;
+            let offset = image.stream_position()?;
+            if image.read_u8()? != MARKER {
+                self.marker = None;
+                self.findings.push(
+                    corrupt(CorruptionKind::InvalidField, "Marker not found").with_offset(offset),
+                );
+                skip_entropy_coded_data(image)?;
+                continue;
+            }
+            let mut marker = image.read_u8()?;
+            while marker == MARKER {
+                marker = image.read_u8()?;
+            }
+            self.marker = Some(marker);
+
+            match marker {
+                EOI => {
+                    if !self.frame {
+                        self.misplaced(marker, offset, "EOI before SOF");
+                    }
+                    if !image.fill_buf()?.is_empty() {
+                        self.findings.push(
+                            corrupt(CorruptionKind::UnexpectedBlock, "Trailing data after EOI")
+                                .with_offset(image.stream_position()?),
+                        );
+                    }
+                    return Ok(());
+                }
+                // Stand-alone markers: TEM, RSTn and SOI
+                0x01 | 0xd0..=0xd7 | SOI => {
+                    self.misplaced(marker, offset, "Unexpected marker");
+                    continue;
+                }
+                _ => (),
+            }
+
+            let length = image.read_u16::<BigEndian>()?;
+            if length < 2 {
+                self.findings.push(
+                    corrupt(
+                        CorruptionKind::InvalidField,
+                        format!("Invalid segment length: {}", length),
+                    )
+                    .with_block(Block::Marker(marker))
+                    .with_offset(offset),
+                );
+                skip_entropy_coded_data(image)?;
+                continue;
+            }
+            read_through(image, u64::from(length - 2))?;
+
+            if is_sof(marker) {
+                self.frame = true;
+            } else if marker == SOS {
+                if !self.frame {
+                    self.misplaced(marker, offset, "SOS before SOF");
+                }
+                skip_entropy_coded_data(image)?;
+            }
+        }
+    }
+
+    fn misplaced(&mut self, marker: u8, offset: u64, message: &'static str) {
+        self.findings.push(
+            corrupt(CorruptionKind::UnexpectedBlock, message)
+                .with_block(Block::Marker(marker))
+                .with_offset(offset),
+        );
+    }
+}
+
+/// Consumes `length` bytes, failing at the end of the image unlike seeking
+fn read_through<R: ?Sized + BufRead>(image: &mut R, mut length: u64) -> ImageResult {
+    while 0 < length {
+        let available = image.fill_buf()?.len();
+        if available == 0 {
+            return Err(corrupt(CorruptionKind::Truncated, "Unexpected end of segment").into());
+        }
+        let n = usize::try_from(length).map_or(available, |it| it.min(available));
+        image.consume(n);
+        length -= n as u64;
+    }
+    Ok(())
+}
+
+/// Skips to the next marker other than RSTn, over the stuffed bytes (0xff00)
+fn skip_entropy_coded_data<R: ?Sized + BufRead + Seek>(image: &mut R) -> ImageResult {
+    loop {
+        let data = image.fill_buf()?;
+        let Some(position) = data.iter().position(|it| *it == MARKER) else {
+            if data.is_empty() {
+                return Err(corrupt(CorruptionKind::Truncated, "Truncated scan").into());
+            }
+            let n = data.len();
+            image.consume(n);
+            continue;
+        };
+        image.consume(position + 1);
+
+        let mut next = image.read_u8()?;
+        while next == MARKER {
+            next = image.read_u8()?;
+        }
+        if next != 0x00 && !(0xd0..=0xd7).contains(&next) {
+            // Leave the marker to the caller
+            image.seek(SeekFrom::Current(-2))?;
+            return Ok(());
+        }
+    }
+}
+
+/// Reads the marker of the next segment, skipping the stuffing bytes
+pub(crate) fn read_marker<R: ?Sized + Read>(image: &mut R) -> ImageResult<u8> {
     let prefix = image.read_u8()?;
     if prefix != MARKER {
-        return Err(ImageError::CorruptImage("Marker not found".into()));
+        return Err(corrupt(CorruptionKind::InvalidField, "Marker not found").into());
     }
 
     // Skip stuffing bytes
@@ -63,16 +742,25 @@ where
         marker = image.read_u8()?;
     }
 
-    let length = image.read_u16::<BigEndian>()? - 2;
+    Ok(marker)
+}
+
+/// Returns the length of the segment data excluding the length field itself
+pub(crate) fn read_length<R: ?Sized + Read>(image: &mut R, marker: u8) -> ImageResult<u16> {
+    let length = image.read_u16::<BigEndian>()?;
+    length.checked_sub(2).ok_or_else(|| {
+        corrupt(
+            CorruptionKind::InvalidField,
+            format!("Invalid segment length: {}", length),
+        )
+        .with_block(Block::Marker(marker))
+        .into()
+    })
+}
 
-    if target_marker(marker) {
-        let mut result = vec![0u8; length as usize];
-        image.read_exact(&mut result)?;
-        Ok((marker, Some(result)))
-    } else {
-        image.seek(SeekFrom::Current(i64::from(length)))?;
-        Ok((marker, None))
-    }
+/// Returns true for the markers without length and data: TEM, RSTn, SOI and EOI
+pub(crate) fn is_stand_alone(marker: u8) -> bool {
+    matches!(marker, 0x01 | 0xd0..=0xd9)
 }
 
 fn is_sof(marker: u8) -> bool {
@@ -81,3 +769,16 @@ fn is_sof(marker: u8) -> bool {
         0xc0 | 0xc1 | 0xc2 | 0xc3 | 0xc5 | 0xc6 | 0xc7 | 0xc9 | 0xca | 0xcb | 0xcd | 0xce | 0xcf
     )
 }
+
+/// Returns true if the marker may precede the frame header (SOF)
+fn is_header_marker(marker: u8) -> bool {
+    matches!(
+        marker,
+        // DHT, DAC, DQT, DRI, EXP, APPn, JPGn and COM
+        0xc4 | 0xcc | 0xdb | 0xdd | 0xdf | 0xe0..=0xef | 0xf0..=0xfe
+    ) || is_sof(marker)
+}
+
+fn corrupt<T: Into<Cow<'static, str>>>(kind: CorruptionKind, message: T) -> Corruption {
+    Corruption::new(Format::Jpeg, kind, message)
+}
@@ -0,0 +1,233 @@
+use alloc::format;
+
+use crate::errors::{Block, Corruption, CorruptionKind, ImageError, ImageResult};
+use crate::io::{self, BufRead, Read, Seek, SeekFrom};
+use crate::types::{Dimensions, Format, ImageMeta, Limit, Limits, LoadOptions, Strictness};
+
+/// State of a load: the usage of `Limits` and the `LoadOptions` to follow
+pub(crate) struct Context<'a> {
+    allocated: usize,
+    blocks: usize,
+    format: Format,
+    limits: &'a Limits,
+    strictness: Strictness,
+}
+
+/// Usage of `Limits` carried over the steps of a resumed load (See `StreamLoader`)
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct Usage {
+    allocated: usize,
+    blocks: usize,
+    bytes_read: u64,
+}
+
+/// Reader counting the bytes consumed against `Limits::max_bytes_read`
+pub(crate) struct Metered<'a, R: ?Sized> {
+    exceeded: bool,
+    image: &'a mut R,
+    remaining: u64,
+}
+
+/// Runs the loader `f` of `format` with `options`
+///
+/// Unexpected EOF is reported as truncated image, and the offset of corruption is filled with the
+/// position of the reader.
+pub(crate) fn limited<R, T, F>(
+    image: &mut R,
+    format: Format,
+    options: &LoadOptions,
+    f: F,
+) -> ImageResult<T>
+where
+    R: ?Sized + BufRead + Seek,
+    F: FnOnce(&mut Metered<'_, R>, &mut Context<'_>) -> ImageResult<T>,
+{
+    resume(image, format, options, &mut Usage::default(), f)
+}
+
+/// Runs `f` as `limited` does, counting on from `usage` and updating it
+pub(crate) fn resume<R, T, F>(
+    image: &mut R,
+    format: Format,
+    options: &LoadOptions,
+    usage: &mut Usage,
+    f: F,
+) -> ImageResult<T>
+where
+    R: ?Sized + BufRead + Seek,
+    F: FnOnce(&mut Metered<'_, R>, &mut Context<'_>) -> ImageResult<T>,
+{
+    let max_bytes_read = options.limits.max_bytes_read;
+    let mut image = Metered {
+        exceeded: false,
+        image,
+        remaining: max_bytes_read.saturating_sub(usage.bytes_read),
+    };
+    let mut cx = Context {
+        allocated: usage.allocated,
+        blocks: usage.blocks,
+        format,
+        limits: &options.limits,
+        strictness: options.strictness,
+    };
+    let result = f(&mut image, &mut cx);
+    *usage = Usage {
+        allocated: cx.allocated,
+        blocks: cx.blocks,
+        bytes_read: max_bytes_read - image.remaining,
+    };
+    match result {
+        Ok(result) => Ok(result),
+        // The error may be reported as I/O error or corrupt image depending on the loader
+        Err(_) if image.exceeded => Err(locate(cx.exceeded(Limit::BytesRead), format, &mut image)),
+        Err(err) => Err(locate(err, format, &mut image)),
+    }
+}
+
+/// Reports unexpected EOF as truncated image, and fills the offset of corruption with the position
+/// of `image`
+pub(crate) fn locate<R: ?Sized + Seek>(
+    err: ImageError,
+    format: Format,
+    image: &mut R,
+) -> ImageError {
+    let corruption = match truncation(err, format) {
+        Ok(it) | Err(ImageError::CorruptImage(it)) => it,
+        Err(err) => return err,
+    };
+    match (corruption.offset, image.stream_position()) {
+        (None, Ok(offset)) => corruption.with_offset(offset).into(),
+        _ => corruption.into(),
+    }
+}
+
+/// Returns `meta` if `result` is ok, or attaches `meta` to the error if the image is truncated
+pub(crate) fn complete(result: ImageResult, meta: ImageMeta) -> ImageResult<ImageMeta> {
+    match result {
+        Ok(()) => Ok(meta),
+        Err(err) => Err(match truncation(err, meta.format) {
+            Ok(corruption) => corruption.with_partial(meta).into(),
+            Err(err) => err,
+        }),
+    }
+}
+
+/// Returns the corruption if `err` means truncated image, otherwise returns `err` back
+pub(crate) fn truncation(err: ImageError, format: Format) -> Result<Corruption, ImageError> {
+    match err {
+        ImageError::Io(err) if err.kind() == io::ErrorKind::UnexpectedEof => Ok(Corruption::new(
+            format,
+            CorruptionKind::Truncated,
+            "Unexpected end of image",
+        )),
+        ImageError::CorruptImage(it) if it.kind == CorruptionKind::Truncated => Ok(it),
+        err => Err(err),
+    }
+}
+
+/// Reports the truncation inside `block` at `offset`, and returns the other errors back
+pub(crate) fn truncated_block(
+    err: ImageError,
+    format: Format,
+    block: Block,
+    offset: u64,
+) -> ImageError {
+    match truncation(err, format) {
+        Ok(corruption) => corruption.with_block(block).with_offset(offset).into(),
+        Err(err) => err,
+    }
+}
+
+impl Context<'_> {
+    /// Reserves `size` bytes before allocating them
+    pub fn alloc(&mut self, size: usize) -> ImageResult {
+        if self.available() < size {
+            return Err(self.exceeded(Limit::Alloc));
+        }
+        self.allocated += size;
+        Ok(())
+    }
+
+    /// Returns the number of bytes allowed to allocate
+    pub fn available(&self) -> usize {
+        self.limits.max_alloc.saturating_sub(self.allocated)
+    }
+
+    /// Counts a chunk, segment, block or frame visited
+    pub fn block(&mut self) -> ImageResult {
+        if self.limits.max_blocks <= self.blocks {
+            return Err(self.exceeded(Limit::Blocks));
+        }
+        self.blocks += 1;
+        Ok(())
+    }
+
+    pub fn dimensions(&self, dimensions: &Dimensions) -> ImageResult {
+        let max = &self.limits.max_dimensions;
+        if max.width < dimensions.width || max.height < dimensions.height {
+            return Err(self.exceeded(Limit::Dimensions));
+        }
+        Ok(())
+    }
+
+    pub fn line(&self, length: usize) -> ImageResult {
+        if self.limits.max_line_length < length {
+            return Err(self.exceeded(Limit::LineLength));
+        }
+        Ok(())
+    }
+
+    pub fn is_lenient(&self) -> bool {
+        self.strictness == Strictness::Lenient
+    }
+
+    pub fn is_strict(&self) -> bool {
+        self.strictness == Strictness::Strict
+    }
+
+    pub fn exceeded(&self, limit: Limit) -> ImageError {
+        Corruption::new(
+            self.format,
+            CorruptionKind::LimitExceeded(limit),
+            format!("Limit exceeded: {}", limit),
+        )
+        .into()
+    }
+}
+
+impl<R: ?Sized + BufRead> Read for Metered<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let available = self.fill_buf()?;
+        let n = available.len().min(buf.len());
+        buf[..n].copy_from_slice(&available[..n]);
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl<R: ?Sized + BufRead> BufRead for Metered<'_, R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.remaining == 0 {
+            self.exceeded = true;
+            return Err(io::Error::other("Limit exceeded: max_bytes_read"));
+        }
+        let available = self.image.fill_buf()?;
+        let n =
+            usize::try_from(self.remaining).map_or(available.len(), |it| it.min(available.len()));
+        Ok(&available[..n])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.image.consume(amt);
+        self.remaining = self.remaining.saturating_sub(amt as u64);
+    }
+}
+
+impl<R: ?Sized + Seek> Seek for Metered<'_, R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.image.seek(pos)
+    }
+}
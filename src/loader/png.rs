@@ -1,26 +1,259 @@
-use std::io::{BufRead, Cursor, Seek, SeekFrom};
+use alloc::borrow::Cow;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
 
-use byteorder::{BigEndian, ReadBytesExt};
+use byteorder::BigEndian;
 
-use crate::errors::{ImageError, ImageResult};
-use crate::types::{Color, Dimensions, Format, ImageMeta};
+use crate::errors::{Block, Corruption, CorruptionKind, ImageError, ImageResult};
+use crate::io::{BufRead, Read, ReadExt, Seek, SeekFrom};
+use crate::loader::limits::{
+    complete, limited, resume, truncated_block, truncation, Context, Usage,
+};
+use crate::loader::resumable::{run, Resumable};
+use crate::loader::{read_data, skip_data};
+use crate::types::{
+    Chromaticities, Cicp, Color, ColorMode, ColorSpace, Comment, CommentSource, ContentLightLevel,
+    Dimensions, Format, ImageMeta, Limit, LoadOptions, MasteringDisplay, Palette, PaletteEntry,
+    PaletteSource, RenderingIntent, Transfer,
+};
 
 const SIGNATURE: [u8; 8] = [0x89, 0x50, 0x4e, 0x47, 0x0d, 0x0a, 0x1a, 0x0a];
+/// CRC-32 of ISO 3309 for each byte value
+const CRC_TABLE: [u32; 256] = crc_table();
+
+/// State of `load` between the chunks, kept for truncated images
+#[derive(Clone)]
+pub(crate) struct MetaReader {
+    animated: bool,
+    color: Color,
+    color_space: ColorSpace,
+    dimensions: Dimensions,
+    frames: usize,
+    order: ChunkOrder,
+}
+
+/// Chunk found by `chunks`
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Chunk {
+    /// Chunk data if requested by `Chunks::with_data`
+    pub data: Option<Vec<u8>>,
+    /// Length of the chunk data
+    pub length: u32,
+    pub name: [u8; 4],
+    /// Offset of the chunk (its length field) in the image
+    pub offset: u64,
+}
+
+/// Iterator over the chunks up to IEND, see `chunks`
+pub struct Chunks<'a, R: ?Sized> {
+    done: bool,
+    image: &'a mut R,
+    options: LoadOptions,
+    usage: Usage,
+    with_data: bool,
+}
+
+/// Position in the chunk sequence, checked in strict mode
+#[derive(Clone, Default)]
+struct ChunkOrder {
+    data: bool,
+    palette: bool,
+    previous: [u8; 4],
+}
 
 pub fn load<R: ?Sized + BufRead + Seek>(image: &mut R) -> ImageResult<ImageMeta> {
+    load_with_options(image, &LoadOptions::default())
+}
+
+pub fn load_with_options<R: ?Sized + BufRead + Seek>(
+    image: &mut R,
+    options: &LoadOptions,
+) -> ImageResult<ImageMeta> {
+    limited(image, Format::Png, options, |image, cx| {
+        run::<MetaReader, _>(image, cx)
+    })
+}
+
+/// Returns the texts of tEXt, zTXt and iTXt chunks
+///
+/// Malformed text chunks are skipped unless `Strictness::Strict` is given.
+pub fn load_comments<R: ?Sized + BufRead + Seek>(image: &mut R) -> ImageResult<Vec<Comment>> {
+    load_comments_with_options(image, &LoadOptions::default())
+}
+
+pub fn load_comments_with_options<R: ?Sized + BufRead + Seek>(
+    image: &mut R,
+    options: &LoadOptions,
+) -> ImageResult<Vec<Comment>> {
+    limited(image, Format::Png, options, |image, cx| {
+        read_signature(image)?;
+        read_header(image)?;
+
+        let mut result = vec![];
+        loop {
+            cx.block()?;
+            let (length, chunk_name) = read_chunk_header(image)?;
+            match &chunk_name {
+                b"tEXt" | b"zTXt" | b"iTXt" => (),
+                b"IEND" => return Ok(result),
+                _ => {
+                    // Data and CRC
+                    image.seek(SeekFrom::Current(i64::from(length) + 4))?;
+                    continue;
+                }
+            }
+            let data = read_chunk_data(image, length, cx)?;
+            let comment = match &chunk_name {
+                b"tEXt" => read_text(&data),
+                b"zTXt" => read_compressed_text(&data, cx),
+                _ => read_international_text(&data, cx),
+            };
+            match comment {
+                Ok(comment) => result.push(comment),
+                Err(err) if cx.is_strict() || is_limit_exceeded(&err) => return Err(err),
+                // One bad chunk does not hide the others
+                Err(_) => (),
+            }
+        }
+    })
+}
+
+/// Verifies all chunks, and returns the findings instead of failing on the first
+///
+/// Checks CRC of every chunk, the chunk ordering, IDAT contiguity and presence of IEND. Errors
+/// are returned for non-PNG images and I/O errors, and truncated images end with a `Truncated`
+/// finding.
+pub fn check_integrity<R: ?Sized + BufRead + Seek>(image: &mut R) -> ImageResult<Vec<Corruption>> {
+    check_integrity_with_options(image, &LoadOptions::default())
+}
+
+pub fn check_integrity_with_options<R: ?Sized + BufRead + Seek>(
+    image: &mut R,
+    options: &LoadOptions,
+) -> ImageResult<Vec<Corruption>> {
+    limited(image, Format::Png, options, |image, cx| {
+        read_signature(image)?;
+
+        let mut findings = vec![];
+        let mut order = ChunkOrder::default();
+        let mut ended = false;
+        while !image.fill_buf()?.is_empty() {
+            if ended {
+                findings.push(
+                    corrupt(CorruptionKind::UnexpectedBlock, "Trailing data after IEND")
+                        .with_offset(image.stream_position()?),
+                );
+                break;
+            }
+            cx.block()?;
+            let offset = image.stream_position()?;
+            let (chunk_name, mismatch) = match read_through(image) {
+                Ok(it) => it,
+                Err(err) => {
+                    findings.push(truncation(err, Format::Png)?.with_offset(offset));
+                    return Ok(findings);
+                }
+            };
+            findings.extend(mismatch);
+            if let Err(it) = order.check(chunk_name) {
+                findings.push(it.with_offset(offset));
+            }
+            ended = chunk_name == *b"IEND";
+        }
+
+        if !ended {
+            findings.push(
+                corrupt(CorruptionKind::Truncated, "IEND not found")
+                    .with_offset(image.stream_position()?),
+            );
+        }
+        Ok(findings)
+    })
+}
+
+/// Walks the chunks up to IEND or the end of the image, without verifying them
+///
+/// See `check_integrity` for the verification. `LoadOptions::default()` applies unless
+/// `Chunks::with_options` is given.
+pub fn chunks<R: ?Sized + BufRead + Seek>(image: &mut R) -> ImageResult<Chunks<'_, R>> {
     read_signature(image)?;
+    Ok(Chunks {
+        done: false,
+        image,
+        options: LoadOptions::default(),
+        usage: Usage::default(),
+        with_data: false,
+    })
+}
 
-    let (dimensions, color) = read_header(image)?;
-    let animation_frames = read_fctls(image)?;
+/// Returns PLTE with the alpha values of tRNS
+pub fn load_palette<R: ?Sized + BufRead + Seek>(image: &mut R) -> ImageResult<Option<Palette>> {
+    load_palette_with_options(image, &LoadOptions::default())
+}
 
-    Ok(ImageMeta {
-        animation_frames,
-        color,
-        dimensions,
-        format: Format::Png,
+pub fn load_palette_with_options<R: ?Sized + BufRead + Seek>(
+    image: &mut R,
+    options: &LoadOptions,
+) -> ImageResult<Option<Palette>> {
+    limited(image, Format::Png, options, |image, cx| {
+        read_palette(image, cx)
     })
 }
 
+fn read_palette<R: ?Sized + BufRead + Seek>(
+    image: &mut R,
+    cx: &mut Context,
+) -> ImageResult<Option<Palette>> {
+    read_signature(image)?;
+    let (_, color) = read_header(image)?;
+
+    let mut entries: Option<Vec<PaletteEntry>> = None;
+    loop {
+        cx.block()?;
+        let (length, chunk_name) = read_chunk_header(image)?;
+        match &chunk_name {
+            b"PLTE" if length <= 256 * 3 => {
+                let mut table = [0u8; 256 * 3];
+                let table = &mut table[..length as usize];
+                image.read_exact(table)?;
+                entries = Some(
+                    table
+                        .chunks_exact(3)
+                        .map(|it| PaletteEntry {
+                            red: it[0],
+                            green: it[1],
+                            blue: it[2],
+                            alpha: 255,
+                        })
+                        .collect(),
+                );
+            }
+            // tRNS of other color types is a single color
+            b"tRNS" if color.mode == ColorMode::Indexed && length <= 256 => {
+                let mut alpha = [0u8; 256];
+                let alpha = &mut alpha[..length as usize];
+                image.read_exact(alpha)?;
+                for (entry, alpha) in entries.iter_mut().flatten().zip(alpha.iter()) {
+                    entry.alpha = *alpha;
+                }
+            }
+            // PLTE and tRNS precede IDAT
+            b"IDAT" | b"IEND" => break,
+            _ => {
+                image.seek(SeekFrom::Current(i64::from(length)))?;
+            }
+        }
+        image.seek(SeekFrom::Current(4))?; // CRC
+    }
+
+    Ok(entries.map(|entries| Palette {
+        entries,
+        source: PaletteSource::Png,
+    }))
+}
+
 fn read_signature<R: ?Sized + BufRead + Seek>(image: &mut R) -> ImageResult {
     let mut signature = [0u8; 8];
     image.read_exact(&mut signature)?;
@@ -33,16 +266,27 @@ fn read_signature<R: ?Sized + BufRead + Seek>(image: &mut R) -> ImageResult {
 fn read_header<R: ?Sized + BufRead + Seek>(image: &mut R) -> ImageResult<(Dimensions, Color)> {
     use crate::types::ColorMode::*;
 
-    let (chunk_name, chunk_data) = read_chunk(image)?;
+    let length = image.read_u32::<BigEndian>()?;
+    let mut chunk_name = [0u8; 4];
+    image.read_exact(&mut chunk_name)?;
     if chunk_name != *b"IHDR" {
-        return Err(ImageError::CorruptImage("Not IHDR".into()));
+        return Err(corrupt(CorruptionKind::UnknownBlock, "Not IHDR")
+            .with_block(Block::Chunk(chunk_name))
+            .into());
+    }
+    if length < 13 {
+        return Err(corrupt(
+            CorruptionKind::Truncated,
+            format!("Too short IHDR: {}", length),
+        )
+        .with_block(Block::Chunk(chunk_name))
+        .into());
     }
-    let mut chunk_data = Cursor::new(chunk_data);
 
-    let width = chunk_data.read_u32::<BigEndian>()?;
-    let height = chunk_data.read_u32::<BigEndian>()?;
-    let resolution = chunk_data.read_u8()?;
-    let color = chunk_data.read_u8()?;
+    let width = image.read_u32::<BigEndian>()?;
+    let height = image.read_u32::<BigEndian>()?;
+    let resolution = image.read_u8()?;
+    let color = image.read_u8()?;
     let (mode, alpha_channel) = match color {
         0 => (Grayscale, false),
         2 => (Rgb, false),
@@ -50,9 +294,12 @@ fn read_header<R: ?Sized + BufRead + Seek>(image: &mut R) -> ImageResult<(Dimens
         4 => (Grayscale, true),
         6 => (Rgb, true),
         _ => {
-            return Err(ImageError::CorruptImage(
-                format!("Invalid color type: {}", color).into(),
-            ))
+            return Err(corrupt(
+                CorruptionKind::InvalidField,
+                format!("Invalid color type: {}", color),
+            )
+            .with_block(Block::Chunk(*b"IHDR"))
+            .into())
         }
     };
     let color = Color {
@@ -64,38 +311,497 @@ fn read_header<R: ?Sized + BufRead + Seek>(image: &mut R) -> ImageResult<(Dimens
     // 1 compression_method
     // 1 filter_method
     // 1 interlace_method
+    // 4 CRC
+    image.seek(SeekFrom::Current(i64::from(length) - 10 + 4))?;
 
     Ok((Dimensions { height, width }, color))
 }
 
-fn read_chunk<R: ?Sized + BufRead + Seek>(image: &mut R) -> ImageResult<([u8; 4], Vec<u8>)> {
+/// Reads the length and the name of the next chunk
+pub(crate) fn read_chunk_header<R: ?Sized + Read>(image: &mut R) -> ImageResult<(u32, [u8; 4])> {
     let length = image.read_u32::<BigEndian>()?;
     let mut chunk_name = [0u8; 4];
     image.read_exact(&mut chunk_name)?;
-    let mut result = vec![0u8; length as usize];
-    image.read_exact(&mut result)?;
+    Ok((length, chunk_name))
+}
+
+/// Reads the data of the chunk after `read_chunk_header`, and skips its CRC
+fn read_chunk_data<R: ?Sized + BufRead + Seek>(
+    image: &mut R,
+    length: u32,
+    cx: &mut Context,
+) -> ImageResult<Vec<u8>> {
+    cx.alloc(length as usize)?;
+    let result = read_data(image, u64::from(length))?;
     // Skip CRC
     image.seek(SeekFrom::Current(4))?;
-    Ok((chunk_name, result))
+    Ok(result)
+}
+
+/// Verifies CRC of the next chunk, and rewinds to the start of it
+fn verify_crc<R: ?Sized + BufRead + Seek>(image: &mut R) -> ImageResult {
+    let start = image.stream_position()?;
+    if let (_, Some(mismatch)) = read_through(image)? {
+        return Err(mismatch.into());
+    }
+    image.seek(SeekFrom::Start(start))?;
+    Ok(())
 }
 
-fn read_fctls<R: ?Sized + BufRead + Seek>(image: &mut R) -> ImageResult<Option<usize>> {
-    let mut result = 0;
+/// Reads through the next chunk, and returns the name and the CRC mismatch if any
+fn read_through<R: ?Sized + BufRead + Seek>(
+    image: &mut R,
+) -> ImageResult<([u8; 4], Option<Corruption>)> {
+    let offset = image.stream_position()?;
+    let length = image.read_u32::<BigEndian>()?;
     let mut chunk_name = [0u8; 4];
-    loop {
-        let length = image.read_u32::<BigEndian>()?;
-        image.read_exact(&mut chunk_name)?;
-        if chunk_name == *b"fcTL" {
-            result += 1;
+    image.read_exact(&mut chunk_name)?;
+
+    let mut crc = update_crc(!0, &chunk_name);
+    let mut rest = u64::from(length);
+    while 0 < rest {
+        let data = image.fill_buf()?;
+        if data.is_empty() {
+            return Err(
+                corrupt(CorruptionKind::Truncated, "Unexpected end of chunk")
+                    .with_block(Block::Chunk(chunk_name))
+                    .into(),
+            );
         }
-        image.seek(SeekFrom::Current(i64::from(length) + 4))?; // 4 means CRC
-        if chunk_name == *b"IEND" {
-            break;
+        let n = usize::try_from(rest).map_or(data.len(), |it| it.min(data.len()));
+        crc = update_crc(crc, &data[..n]);
+        image.consume(n);
+        rest -= n as u64;
+    }
+    let expected = image.read_u32::<BigEndian>()?;
+    let mismatch = (!crc != expected).then(|| {
+        corrupt(
+            CorruptionKind::BadChecksum,
+            format!("CRC mismatch: {:08x} (expected {:08x})", !crc, expected),
+        )
+        .with_block(Block::Chunk(chunk_name))
+        .with_offset(offset)
+    });
+    Ok((chunk_name, mismatch))
+}
+
+fn update_crc(crc: u32, data: &[u8]) -> u32 {
+    data.iter().fold(crc, |crc, it| {
+        CRC_TABLE[((crc ^ u32::from(*it)) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+const fn crc_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut n = 0;
+    while n < 256 {
+        let mut crc = n as u32;
+        let mut k = 0;
+        while k < 8 {
+            crc = if crc & 1 == 1 {
+                0xedb8_8320 ^ (crc >> 1)
+            } else {
+                crc >> 1
+            };
+            k += 1;
+        }
+        table[n] = crc;
+        n += 1;
+    }
+    table
+}
+
+impl<R: ?Sized> Chunks<'_, R> {
+    /// Reads the data of each chunk
+    pub fn with_data(self) -> Self {
+        Chunks {
+            with_data: true,
+            ..self
+        }
+    }
+
+    /// Applies `options` to the whole walk
+    pub fn with_options(self, options: LoadOptions) -> Self {
+        Chunks { options, ..self }
+    }
+}
+
+impl<R: ?Sized + BufRead + Seek> Iterator for Chunks<'_, R> {
+    type Item = ImageResult<Chunk>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
         }
+
+        let with_data = self.with_data;
+        let result = resume(
+            self.image,
+            Format::Png,
+            &self.options,
+            &mut self.usage,
+            |image, cx| {
+                if image.fill_buf()?.is_empty() {
+                    return Ok(None);
+                }
+                cx.block()?;
+                read_chunk_entry(image, cx, with_data).map(Some)
+            },
+        );
+        self.done = !matches!(&result, Ok(Some(it)) if it.name != *b"IEND");
+        result.transpose()
     }
-    if 0 < result {
-        Ok(Some(result))
+}
+
+/// Reads the next chunk for `Chunks`
+fn read_chunk_entry<R: ?Sized + BufRead + Seek>(
+    image: &mut R,
+    cx: &mut Context,
+    with_data: bool,
+) -> ImageResult<Chunk> {
+    let offset = image.stream_position()?;
+    let (length, name) = read_chunk_header(image)?;
+    let data = if with_data {
+        cx.alloc(length as usize)?;
+        read_data(image, u64::from(length)).map(Some)
     } else {
-        Ok(None)
+        skip_data(image, u64::from(length)).map(|_| None)
+    };
+    let data = data
+        .and_then(|data| skip_data(image, 4).map(|_| data)) // CRC
+        .map_err(|err| truncated_block(err, Format::Png, Block::Chunk(name), offset))?;
+    Ok(Chunk {
+        data,
+        length,
+        name,
+        offset,
+    })
+}
+
+impl ChunkOrder {
+    /// Checks the ordering constraints of PNG and APNG specifications
+    fn check(&mut self, chunk_name: [u8; 4]) -> Result<(), Corruption> {
+        let first = self.previous == [0; 4];
+        let misplaced = match &chunk_name {
+            b"IHDR" if first => None,
+            b"IHDR" => Some("Duplicate IHDR"),
+            _ if first => Some("Not IHDR"),
+            b"cHRM" | b"cICP" | b"cLLI" | b"gAMA" | b"iCCP" | b"mDCv" | b"sBIT" | b"sRGB"
+                if self.palette || self.data =>
+            {
+                Some("Misplaced chunk")
+            }
+            b"PLTE" if self.palette || self.data => Some("Misplaced chunk"),
+            b"acTL" | b"bKGD" | b"hIST" | b"pHYs" | b"sPLT" | b"tRNS" if self.data => {
+                Some("Misplaced chunk")
+            }
+            b"IDAT" if self.data && self.previous != *b"IDAT" => Some("Non-contiguous IDAT"),
+            b"IEND" if !self.data => Some("IDAT not found"),
+            _ => None,
+        };
+
+        self.data |= chunk_name == *b"IDAT";
+        self.palette |= chunk_name == *b"PLTE";
+        self.previous = chunk_name;
+        match misplaced {
+            Some(message) => Err(corrupt(CorruptionKind::UnexpectedBlock, message)
+                .with_block(Block::Chunk(chunk_name))),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Resumable for MetaReader {
+    fn open<R: ?Sized + BufRead + Seek>(image: &mut R, cx: &mut Context) -> ImageResult<Self> {
+        read_signature(image)?;
+        if cx.is_strict() {
+            verify_crc(image)?;
+        }
+
+        let (dimensions, color) = read_header(image)?;
+        cx.dimensions(&dimensions)?;
+        Ok(MetaReader {
+            animated: false,
+            color,
+            color_space: ColorSpace::default(),
+            dimensions,
+            frames: 0,
+            order: ChunkOrder {
+                previous: *b"IHDR",
+                ..ChunkOrder::default()
+            },
+        })
+    }
+
+    /// Counts fcTL chunks and reads the color space chunks
+    ///
+    /// Non-animated images are read up to the first IDAT, because acTL and the color space chunks
+    /// precede it. Strict mode reads all chunks to verify them.
+    fn step<R: ?Sized + BufRead + Seek>(
+        &mut self,
+        image: &mut R,
+        cx: &mut Context,
+    ) -> ImageResult<bool> {
+        if cx.is_lenient() && image.fill_buf()?.is_empty() {
+            // Missing IEND
+            return Ok(true);
+        }
+        cx.block()?;
+        if cx.is_strict() {
+            verify_crc(image)?;
+        }
+        let (length, chunk_name) = read_chunk_header(image)?;
+        if cx.is_strict() {
+            self.order.check(chunk_name)?;
+        }
+        let color_space = &mut self.color_space;
+        let consumed = match (&chunk_name, length) {
+            (b"cHRM", 32) => {
+                color_space.chromaticities = Some(read_chromaticities(image)?);
+                true
+            }
+            (b"cICP", 4) => {
+                color_space.cicp = Some(read_cicp(image)?);
+                true
+            }
+            (b"cLLI", 8) => {
+                color_space.content_light_level = Some(ContentLightLevel {
+                    max_content_light_level: image.read_u32::<BigEndian>()?,
+                    max_frame_average_light_level: image.read_u32::<BigEndian>()?,
+                });
+                true
+            }
+            (b"gAMA", 4) => {
+                color_space.gamma = Some(image.read_u32::<BigEndian>()?);
+                true
+            }
+            (b"mDCv", 24) => {
+                color_space.mastering_display = Some(read_mastering_display(image)?);
+                true
+            }
+            (b"sRGB", 1) => {
+                color_space.rendering_intent = read_rendering_intent(image.read_u8()?);
+                true
+            }
+            (b"acTL", _) => {
+                self.animated = true;
+                false
+            }
+            (b"fcTL", _) => {
+                self.frames += 1;
+                false
+            }
+            (b"IDAT", _) if !self.animated && !cx.is_strict() => return Ok(true),
+            (b"IEND", 1..) if cx.is_strict() => {
+                return Err(corrupt(
+                    CorruptionKind::InvalidField,
+                    format!("Non-empty IEND: {}", length),
+                )
+                .with_block(Block::Chunk(chunk_name))
+                .into());
+            }
+            _ => false,
+        };
+        let rest = if consumed { 0 } else { i64::from(length) };
+        image.seek(SeekFrom::Current(rest + 4))?; // 4 means CRC
+        if chunk_name != *b"IEND" {
+            return Ok(false);
+        }
+
+        if cx.is_strict() && !image.fill_buf()?.is_empty() {
+            return Err(
+                corrupt(CorruptionKind::UnexpectedBlock, "Trailing data after IEND").into(),
+            );
+        }
+        Ok(true)
     }
+
+    fn dimensions(&self) -> Option<&Dimensions> {
+        Some(&self.dimensions)
+    }
+
+    fn finish(self, result: ImageResult) -> ImageResult<ImageMeta> {
+        let animation_frames = if 0 < self.frames {
+            Some(self.frames)
+        } else {
+            None
+        };
+        let color_space = if self.color_space == ColorSpace::default() {
+            None
+        } else {
+            Some(ColorSpace {
+                transfer: resolve_transfer(&self.color_space),
+                ..self.color_space
+            })
+        };
+        let meta = ImageMeta {
+            animation_frames,
+            color: self.color,
+            color_space,
+            dimensions: self.dimensions,
+            format: Format::Png,
+        };
+        complete(result, meta)
+    }
+}
+
+fn read_chromaticities<R: ?Sized + BufRead + Seek>(image: &mut R) -> ImageResult<Chromaticities> {
+    let mut values = [0u32; 8];
+    image.read_u32_into::<BigEndian>(&mut values)?;
+    Ok(Chromaticities {
+        white: [values[0], values[1]],
+        red: [values[2], values[3]],
+        green: [values[4], values[5]],
+        blue: [values[6], values[7]],
+    })
+}
+
+fn read_cicp<R: ?Sized + BufRead + Seek>(image: &mut R) -> ImageResult<Cicp> {
+    let mut values = [0u8; 4];
+    image.read_exact(&mut values)?;
+    Ok(Cicp {
+        color_primaries: values[0],
+        transfer_characteristics: values[1],
+        matrix_coefficients: values[2],
+        full_range: values[3] != 0,
+    })
+}
+
+fn read_mastering_display<R: ?Sized + BufRead + Seek>(
+    image: &mut R,
+) -> ImageResult<MasteringDisplay> {
+    let mut values = [0u16; 8];
+    image.read_u16_into::<BigEndian>(&mut values)?;
+    Ok(MasteringDisplay {
+        primaries: [
+            [values[0], values[1]],
+            [values[2], values[3]],
+            [values[4], values[5]],
+        ],
+        white_point: [values[6], values[7]],
+        max_luminance: image.read_u32::<BigEndian>()?,
+        min_luminance: image.read_u32::<BigEndian>()?,
+    })
+}
+
+fn read_rendering_intent(value: u8) -> Option<RenderingIntent> {
+    use crate::types::RenderingIntent::*;
+
+    match value {
+        0 => Some(Perceptual),
+        1 => Some(RelativeColorimetric),
+        2 => Some(Saturation),
+        3 => Some(AbsoluteColorimetric),
+        _ => None,
+    }
+}
+
+/// cICP overrides sRGB, and sRGB overrides gAMA
+fn resolve_transfer(color_space: &ColorSpace) -> Option<Transfer> {
+    if let Some(cicp) = color_space.cicp {
+        return Some(Transfer::from_cicp(cicp.transfer_characteristics));
+    }
+    if color_space.rendering_intent.is_some() {
+        return Some(Transfer::Srgb);
+    }
+    color_space.gamma.map(|gamma| {
+        if gamma == 100000 {
+            Transfer::Linear
+        } else {
+            Transfer::Gamma(gamma)
+        }
+    })
+}
+
+fn read_text(data: &[u8]) -> ImageResult<Comment> {
+    let (keyword, text) = split_keyword(data, *b"tEXt")?;
+    Ok(Comment {
+        keyword: Some(keyword),
+        source: CommentSource::PngText,
+        text: decode_latin1(text),
+    })
+}
+
+fn read_compressed_text(data: &[u8], cx: &mut Context) -> ImageResult<Comment> {
+    let (keyword, data) = split_keyword(data, *b"zTXt")?;
+    // The first byte is the compression method
+    let text = inflate(data.get(1..).unwrap_or_default(), *b"zTXt", cx)?;
+    Ok(Comment {
+        keyword: Some(keyword),
+        source: CommentSource::PngCompressedText,
+        text: decode_latin1(&text),
+    })
+}
+
+fn read_international_text(data: &[u8], cx: &mut Context) -> ImageResult<Comment> {
+    const ITXT: [u8; 4] = *b"iTXt";
+
+    let (keyword, data) = split_keyword(data, ITXT)?;
+    let truncated = || -> ImageError {
+        corrupt(CorruptionKind::Truncated, "Truncated iTXt")
+            .with_block(Block::Chunk(ITXT))
+            .into()
+    };
+    let (compressed, data) = match data {
+        // 1 Compression flag, 1 Compression method
+        [flag, _, rest @ ..] => (*flag != 0, rest),
+        _ => return Err(truncated()),
+    };
+    let mut fields = data.splitn(3, |it| *it == 0);
+    let (_language, _translated_keyword, text) = match (fields.next(), fields.next(), fields.next())
+    {
+        (Some(language), Some(translated), Some(text)) => (language, translated, text),
+        _ => return Err(truncated()),
+    };
+    let text = if compressed {
+        String::from_utf8_lossy(&inflate(text, ITXT, cx)?).into_owned()
+    } else {
+        String::from_utf8_lossy(text).into_owned()
+    };
+    Ok(Comment {
+        keyword: Some(keyword),
+        source: CommentSource::PngInternationalText,
+        text,
+    })
+}
+
+fn split_keyword(data: &[u8], chunk_name: [u8; 4]) -> ImageResult<(String, &[u8])> {
+    let separator = data.iter().position(|it| *it == 0).ok_or_else(|| {
+        corrupt(CorruptionKind::Truncated, "Keyword not terminated")
+            .with_block(Block::Chunk(chunk_name))
+    })?;
+    Ok((decode_latin1(&data[..separator]), &data[separator + 1..]))
+}
+
+fn is_limit_exceeded(err: &ImageError) -> bool {
+    matches!(
+        err.corruption_kind(),
+        Some(CorruptionKind::LimitExceeded(_))
+    )
+}
+
+fn decode_latin1(data: &[u8]) -> String {
+    data.iter().map(|it| char::from(*it)).collect()
+}
+
+/// Decompresses within the allocation limit against zlib bombs
+fn inflate(data: &[u8], chunk_name: [u8; 4], cx: &mut Context) -> ImageResult<Vec<u8>> {
+    use miniz_oxide::inflate::TINFLStatus;
+
+    let result = miniz_oxide::inflate::decompress_to_vec_zlib_with_limit(data, cx.available())
+        .map_err(|err| match err.status {
+            TINFLStatus::HasMoreOutput => cx.exceeded(Limit::Alloc),
+            _ => corrupt(
+                CorruptionKind::InvalidField,
+                format!("Invalid compressed text: {}", err),
+            )
+            .with_block(Block::Chunk(chunk_name))
+            .into(),
+        })?;
+    cx.alloc(result.len())?;
+    Ok(result)
+}
+
+fn corrupt<T: Into<Cow<'static, str>>>(kind: CorruptionKind, message: T) -> Corruption {
+    Corruption::new(Format::Png, kind, message)
 }
@@ -1,27 +1,82 @@
-use std::io::{BufRead, Seek};
+use alloc::borrow::Cow;
+use alloc::format;
 
-use byteorder::{BigEndian, ReadBytesExt};
+use byteorder::BigEndian;
 
-use crate::errors::{ImageError, ImageResult};
-use crate::types::{Color, Dimensions, Format, ImageMeta};
+use crate::errors::{Corruption, CorruptionKind, ImageError, ImageResult};
+use crate::io::{BufRead, ReadExt, Seek, SeekFrom};
+use crate::loader::limits::limited;
+use crate::types::{Color, ColorSpace, Dimensions, Format, ImageMeta, LoadOptions, Transfer};
 
 // See: https://github.com/phoboslab/qoi/blob/master/qoi.h
 
 const SIGNATURE: [u8; 4] = [0x71, 0x6f, 0x69, 0x66];
+const END_MARKER: [u8; 8] = [0, 0, 0, 0, 0, 0, 0, 1];
+const PIXELS_MAX: u64 = 400_000_000;
 
 pub fn load<R: ?Sized + BufRead + Seek>(image: &mut R) -> ImageResult<ImageMeta> {
-    read_signature(image)?;
+    load_with_options(image, &LoadOptions::default())
+}
+
+pub fn load_with_options<R: ?Sized + BufRead + Seek>(
+    image: &mut R,
+    options: &LoadOptions,
+) -> ImageResult<ImageMeta> {
+    limited(image, Format::Qoi, options, |image, cx| {
+        read_signature(image)?;
 
-    let (dimensions, color) = read_header(image)?;
+        let (dimensions, color) = read_header(image)?;
+        cx.dimensions(&dimensions)?;
+        let color_space = read_color_space(image.read_u8()?).map(|transfer| ColorSpace {
+            transfer: Some(transfer),
+            ..ColorSpace::default()
+        });
 
-    Ok(ImageMeta {
-        animation_frames: None,
-        color,
-        dimensions,
-        format: Format::Qoi,
+        Ok(ImageMeta {
+            animation_frames: None,
+            color,
+            color_space,
+            dimensions,
+            format: Format::Qoi,
+        })
     })
 }
 
+/// Loads the image checking the header values and the end marker as `qoi.h` does
+pub fn verify<R: ?Sized + BufRead + Seek>(image: &mut R) -> ImageResult<ImageMeta> {
+    let meta = load(image)?;
+
+    let Dimensions { width, height } = meta.dimensions;
+    if width == 0 || height == 0 {
+        return Err(corrupt(
+            CorruptionKind::InvalidField,
+            format!("Empty image: {}x{}", width, height),
+        )
+        .into());
+    }
+    if PIXELS_MAX <= u64::from(width) * u64::from(height) {
+        return Err(corrupt(
+            CorruptionKind::InvalidField,
+            format!("Too many pixels: {}x{}", width, height),
+        )
+        .into());
+    }
+    if meta.color_space.is_none() {
+        return Err(corrupt(CorruptionKind::InvalidField, "Invalid colorspace").into());
+    }
+
+    let mut end_marker = [0u8; 8];
+    image.seek(SeekFrom::End(-8))?;
+    image.read_exact(&mut end_marker)?;
+    if end_marker != END_MARKER {
+        return Err(corrupt(CorruptionKind::Truncated, "End marker not found")
+            .with_offset(image.stream_position()? - 8)
+            .into());
+    }
+
+    Ok(meta)
+}
+
 fn read_signature<R: ?Sized + BufRead + Seek>(image: &mut R) -> ImageResult {
     let mut signature = [0u8; 4];
     image.read_exact(&mut signature)?;
@@ -41,9 +96,11 @@ fn read_header<R: ?Sized + BufRead + Seek>(image: &mut R) -> ImageResult<(Dimens
         3 => (Rgb, false),
         4 => (Rgb, true),
         _ => {
-            return Err(ImageError::CorruptImage(
-                format!("Invalid color type: {}", color).into(),
-            ))
+            return Err(corrupt(
+                CorruptionKind::InvalidField,
+                format!("Invalid color type: {}", color),
+            )
+            .into())
         }
     };
     let color = Color {
@@ -54,3 +111,16 @@ fn read_header<R: ?Sized + BufRead + Seek>(image: &mut R) -> ImageResult<(Dimens
 
     Ok((Dimensions { height, width }, color))
 }
+
+/// The alpha channel is always linear
+fn read_color_space(value: u8) -> Option<Transfer> {
+    match value {
+        0 => Some(Transfer::Srgb),
+        1 => Some(Transfer::Linear),
+        _ => None,
+    }
+}
+
+fn corrupt<T: Into<Cow<'static, str>>>(kind: CorruptionKind, message: T) -> Corruption {
+    Corruption::new(Format::Qoi, kind, message)
+}
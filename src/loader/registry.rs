@@ -0,0 +1,277 @@
+//! Registry of the format loaders, for loading custom formats and removing built-in ones
+
+use alloc::boxed::Box;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::any::TypeId;
+use core::{fmt, slice};
+
+use crate::errors::{ImageError, ImageResult, Unsupported};
+use crate::io::{BufRead, Seek, SeekFrom};
+use crate::loader::{bmp, gif, hdr, jpeg, peek_signature, png, qoi, webp, SIGNATURE_LENGTH};
+use crate::types::{Format, ImageMeta, LoadOptions};
+
+/// Reader given to `FormatLoader`
+pub trait ImageReader: BufRead + Seek {}
+
+impl<T: ?Sized + BufRead + Seek> ImageReader for T {}
+
+/// Loader of an image format
+pub trait FormatLoader: Send + Sync {
+    /// Format of the images loaded (`Format::Custom` for the application's formats)
+    fn format(&self) -> Format;
+
+    /// Returns true if `signature`, the leading bytes of the image, is of the format
+    ///
+    /// `signature` may be shorter than the image if fewer bytes are available.
+    fn detect(&self, signature: &[u8]) -> bool;
+
+    /// Loads from the start of `image`
+    ///
+    /// Fails with `ImageError::InvalidSignature` if the image is of another format, so that the
+    /// next loader is tried.
+    fn load(&self, image: &mut dyn ImageReader, options: &LoadOptions) -> ImageResult<ImageMeta>;
+}
+
+/// Format loaders in the order of trial
+///
+/// `Registry::new` has the built-in formats, to which the loaders of the application can be
+/// added. The functions of this crate (e.g. `load_with_options`) use the registry of
+/// `LoadOptions::registry`, and the built-in formats without one.
+pub struct Registry {
+    entries: Vec<Entry>,
+}
+
+pub(crate) struct Entry {
+    /// True for the built-in loaders, which `StreamLoader` may run block by block
+    builtin: bool,
+    loader: Box<dyn FormatLoader>,
+    priority: i32,
+}
+
+/// Loader of a built-in format
+#[derive(Clone, Copy)]
+pub(crate) struct Builtin(Format);
+
+/// Loaders of a registry or the built-in ones, in the order of trial
+#[derive(Clone)]
+pub(crate) enum Loaders<'a> {
+    Builtin(slice::Iter<'a, Builtin>),
+    Registry(slice::Iter<'a, Entry>),
+}
+
+/// Built-in loaders in the order of detection
+static BUILTIN: [Builtin; 7] = [
+    Builtin(Format::Jpeg),
+    Builtin(Format::Gif),
+    Builtin(Format::Png),
+    Builtin(Format::Bmp),
+    Builtin(Format::Webp),
+    Builtin(Format::Hdr),
+    Builtin(Format::Qoi),
+];
+
+impl Registry {
+    /// Registry of the built-in formats with priority 0
+    pub fn new() -> Self {
+        let mut registry = Self::empty();
+        for it in &BUILTIN {
+            registry.register(*it, 0);
+        }
+        registry
+    }
+
+    /// Registry without any format
+    pub fn empty() -> Self {
+        Registry {
+            entries: Vec::new(),
+        }
+    }
+
+    /// Adds `loader`
+    ///
+    /// Loaders of higher priority are tried first, and the ones of the same priority in the order
+    /// of registration.
+    pub fn register<L: FormatLoader + 'static>(&mut self, loader: L, priority: i32) -> &mut Self {
+        let index = self.entries.partition_point(|it| priority <= it.priority);
+        let entry = Entry {
+            builtin: TypeId::of::<L>() == TypeId::of::<Builtin>(),
+            loader: Box::new(loader),
+            priority,
+        };
+        self.entries.insert(index, entry);
+        self
+    }
+
+    /// Removes the loaders of `format`
+    pub fn disable(&mut self, format: Format) -> &mut Self {
+        self.entries.retain(|it| it.loader.format() != format);
+        self
+    }
+
+    /// Returns the formats in the order of trial
+    pub fn formats(&self) -> Vec<Format> {
+        self.loaders().map(|it| it.format()).collect()
+    }
+
+    /// Detects the format from the signature at the start of `buffer`
+    pub fn detect_format(&self, buffer: &[u8]) -> Option<Format> {
+        self.loaders().detect(buffer)
+    }
+
+    /// Tries the loader detecting the signature first, then the others in order
+    pub fn load<R: ?Sized + BufRead + Seek>(
+        &self,
+        image: &mut R,
+        options: &LoadOptions,
+    ) -> ImageResult<ImageMeta> {
+        self.loaders().load(image, options)
+    }
+
+    /// Loads with the first loader of `format`
+    pub fn load_with_format<R: ?Sized + BufRead + Seek>(
+        &self,
+        image: &mut R,
+        format: Format,
+        options: &LoadOptions,
+    ) -> ImageResult<ImageMeta> {
+        self.loaders().load_with_format(image, format, options)
+    }
+
+    fn loaders(&self) -> Loaders<'_> {
+        Loaders::Registry(self.entries.iter())
+    }
+}
+
+impl Default for Registry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for Registry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Registry")
+            .field("formats", &self.formats())
+            .finish()
+    }
+}
+
+impl FormatLoader for Builtin {
+    fn format(&self) -> Format {
+        self.0
+    }
+
+    fn detect(&self, signature: &[u8]) -> bool {
+        super::detect_builtin_format(signature) == Some(self.0)
+    }
+
+    fn load(&self, image: &mut dyn ImageReader, options: &LoadOptions) -> ImageResult<ImageMeta> {
+        match self.0 {
+            Format::Bmp => bmp::load_with_options(image, options),
+            Format::Gif => gif::load_with_options(image, options),
+            Format::Hdr => hdr::load_with_options(image, options),
+            Format::Jpeg => jpeg::load_with_options(image, options),
+            Format::Png => png::load_with_options(image, options),
+            Format::Qoi => qoi::load_with_options(image, options),
+            Format::Webp => webp::load_with_options(image, options),
+            Format::Custom(_) => Err(ImageError::InvalidSignature),
+        }
+    }
+}
+
+/// Returns the built-in loaders used by the functions of this crate
+pub(crate) fn builtin() -> Loaders<'static> {
+    Loaders::Builtin(BUILTIN.iter())
+}
+
+/// Returns the loaders of `options.registry`, or the built-in ones
+pub(crate) fn loaders(options: &LoadOptions) -> Loaders<'_> {
+    match &options.registry {
+        Some(registry) => registry.loaders(),
+        None => builtin(),
+    }
+}
+
+impl<'a> Loaders<'a> {
+    pub fn detect(mut self, buffer: &[u8]) -> Option<Format> {
+        self.find(|it| it.detect(buffer)).map(|it| it.format())
+    }
+
+    /// Tries the loader detecting the signature first, then the others in order
+    pub fn load<R: ?Sized + BufRead + Seek>(
+        self,
+        mut image: &mut R,
+        options: &LoadOptions,
+    ) -> ImageResult<ImageMeta> {
+        let mut signature = [0u8; SIGNATURE_LENGTH];
+        let length = peek_signature(image, &mut signature)?;
+        let signature = &signature[..length];
+        let detected = self.clone().position(|it| it.detect(signature));
+        let others = self
+            .clone()
+            .enumerate()
+            .filter(|(i, _)| Some(*i) != detected);
+        let loaders = detected
+            .and_then(|i| self.clone().nth(i))
+            .into_iter()
+            .chain(others.map(|(_, it)| it));
+        for loader in loaders {
+            match loader.load(&mut image, options) {
+                Ok(meta) => return Ok(meta),
+                Err(ImageError::InvalidSignature) => {
+                    image.seek(SeekFrom::Start(0))?;
+                }
+                otherwise => return otherwise,
+            }
+        }
+        Err(self.unsupported(signature))
+    }
+
+    pub fn load_with_format<R: ?Sized + BufRead + Seek>(
+        mut self,
+        mut image: &mut R,
+        format: Format,
+        options: &LoadOptions,
+    ) -> ImageResult<ImageMeta> {
+        match self.find(|it| it.format() == format) {
+            Some(loader) => loader.load(&mut image, options),
+            None => Err(ImageError::Unsupported(Unsupported {
+                signature: signature_of(image.fill_buf()?),
+                tried: vec![],
+            })),
+        }
+    }
+
+    /// Returns true if the first loader of `format` is the built-in one
+    pub fn is_builtin(&self, format: Format) -> bool {
+        match self.clone() {
+            Loaders::Builtin(mut it) => it.any(|it| it.0 == format),
+            Loaders::Registry(mut it) => it
+                .find(|it| it.loader.format() == format)
+                .is_some_and(|it| it.builtin),
+        }
+    }
+
+    pub fn unsupported(self, signature: &[u8]) -> ImageError {
+        ImageError::Unsupported(Unsupported {
+            signature: signature_of(signature),
+            tried: self.map(|it| it.format()).collect(),
+        })
+    }
+}
+
+impl<'a> Iterator for Loaders<'a> {
+    type Item = &'a dyn FormatLoader;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            Loaders::Builtin(it) => it.next().map(|it| it as &dyn FormatLoader),
+            Loaders::Registry(it) => it.next().map(|it| &*it.loader),
+        }
+    }
+}
+
+fn signature_of(buffer: &[u8]) -> Vec<u8> {
+    buffer[..buffer.len().min(SIGNATURE_LENGTH)].to_vec()
+}
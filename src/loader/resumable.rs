@@ -0,0 +1,42 @@
+use crate::errors::ImageResult;
+use crate::io::{BufRead, Seek};
+use crate::loader::limits::Context;
+use crate::types::{Dimensions, ImageMeta};
+
+/// Loader reading block by block, which `StreamLoader` suspends between the blocks
+///
+/// The state must not refer to the image, so that the next block can be read from another reader
+/// positioned at the same offset.
+pub(crate) trait Resumable: Clone + Sized {
+    /// Reads the signature and the header
+    fn open<R: ?Sized + BufRead + Seek>(image: &mut R, cx: &mut Context) -> ImageResult<Self>;
+
+    /// Reads the next block, and returns true if no more blocks are needed
+    fn step<R: ?Sized + BufRead + Seek>(
+        &mut self,
+        image: &mut R,
+        cx: &mut Context,
+    ) -> ImageResult<bool>;
+
+    /// Returns the dimensions if the header tells them
+    fn dimensions(&self) -> Option<&Dimensions>;
+
+    /// Returns the meta of the blocks read, or attaches it to `result` if the image is truncated
+    fn finish(self, result: ImageResult) -> ImageResult<ImageMeta>;
+}
+
+/// Loads the whole image with `T` in one go
+pub(crate) fn run<T, R>(image: &mut R, cx: &mut Context) -> ImageResult<ImageMeta>
+where
+    T: Resumable,
+    R: ?Sized + BufRead + Seek,
+{
+    let mut loader = T::open(image, cx)?;
+    loop {
+        match loader.step(image, cx) {
+            Ok(false) => (),
+            Ok(true) => return loader.finish(Ok(())),
+            Err(err) => return loader.finish(Err(err)),
+        }
+    }
+}
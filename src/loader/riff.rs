@@ -1,22 +1,40 @@
-#![allow(unused_imports)]
+//! Reader of RIFF container (WebP)
 
-use std::io::{BufRead, BufReader, Cursor, Read, Seek, SeekFrom, Take};
-
-use byteorder::{LittleEndian, ReadBytesExt};
+use byteorder::LittleEndian;
 
 use crate::errors::{ImageError, ImageResult};
+use crate::io::{BufRead, Read, ReadExt, Seek, SeekFrom, Take};
+
+/// Walks the top-level chunks of RIFF
+///
+/// `T` may be a reference (e.g. `&mut BufReader<File>`).
+pub struct RiffReader<T: BufRead + Seek> {
+    buffer: T,
+    form_type: [u8; 4],
+    /// A chunk exceeded the size in the RIFF header
+    overrun: bool,
+    remain: usize,
+    skip_for: usize,
+}
 
-pub struct RiffReader<T: Read + Seek> {
-    buffer: BufReader<T>,
+/// `RiffReader` apart from the buffer, to resume on another buffer at the same position
+#[derive(Clone, Copy)]
+pub(crate) struct RiffState {
     form_type: [u8; 4],
+    overrun: bool,
     remain: usize,
     skip_for: usize,
 }
 
+/// Chunk being read by `RiffReader`, which reads the chunk data
+///
+/// The rest of the data is skipped by the next `RiffReader::read_chunk`.
 pub struct Chunk<'a> {
     skip_for: &'a mut usize,
     identifier: [u8; 4],
     buffer: Take<&'a mut dyn BufRead>,
+    offset: u64,
+    size: u32,
 }
 
 impl<T: BufRead + Seek> RiffReader<T> {
@@ -24,9 +42,8 @@ impl<T: BufRead + Seek> RiffReader<T> {
         &self.form_type
     }
 
-    pub fn open(buffer: T) -> ImageResult<Self> {
-        let mut buffer = BufReader::new(buffer);
-
+    /// Reads the RIFF header, and fails with `ImageError::InvalidSignature` if it is not RIFF
+    pub fn open(mut buffer: T) -> ImageResult<Self> {
         let mut signature = [0u8; 4];
         buffer.read_exact(&mut signature)?;
         if &signature != b"RIFF" {
@@ -42,44 +59,99 @@ impl<T: BufRead + Seek> RiffReader<T> {
         Ok(RiffReader {
             buffer,
             form_type,
+            overrun: false,
             remain,
             skip_for: 0,
         })
     }
 
-    pub fn read_chunk(&mut self) -> ImageResult<Option<Chunk>> {
-        if 0 < self.skip_for {
-            self.buffer.seek(SeekFrom::Current(self.skip_for as i64))?;
-        }
+    /// Returns true if a chunk exceeded the size in the RIFF header
+    pub fn is_overrun(&self) -> bool {
+        self.overrun
+    }
+
+    /// Skips the rest of the current chunk, and returns true if no data follows
+    pub fn is_at_end(&mut self) -> ImageResult<bool> {
+        self.skip()?;
+        Ok(self.buffer.fill_buf()?.is_empty())
+    }
+
+    /// Returns the next chunk, or `None` at the end of the size in the RIFF header
+    pub fn read_chunk(&mut self) -> ImageResult<Option<Chunk<'_>>> {
+        self.skip()?;
 
         if self.remain == 0 {
             return Ok(None);
         }
 
+        let offset = self.buffer.stream_position()?;
         let mut identifier = [0u8; 4];
         self.buffer.read_exact(&mut identifier)?;
 
-        let size = self.buffer.read_u32::<LittleEndian>()? as usize;
-        self.remain = self.remain.saturating_sub(size + 8);
-        let buffer = (&mut self.buffer as &mut dyn BufRead).take(size as u64);
-        self.skip_for = size;
+        let size = self.buffer.read_u32::<LittleEndian>()?;
+        // Chunks of odd size are followed by a pad byte
+        let padded = (size as usize).saturating_add(size as usize & 1);
+        self.overrun |= self.remain < padded.saturating_add(8);
+        self.remain = self.remain.saturating_sub(padded.saturating_add(8));
+        let buffer = (&mut self.buffer as &mut dyn BufRead).take(u64::from(size));
+        self.skip_for = padded;
 
         Ok(Some(Chunk {
             buffer,
             identifier,
+            offset,
+            size,
             skip_for: &mut self.skip_for,
         }))
     }
+
+    pub(crate) fn resume(buffer: T, state: RiffState) -> Self {
+        RiffReader {
+            buffer,
+            form_type: state.form_type,
+            overrun: state.overrun,
+            remain: state.remain,
+            skip_for: state.skip_for,
+        }
+    }
+
+    pub(crate) fn suspend(self) -> RiffState {
+        RiffState {
+            form_type: self.form_type,
+            overrun: self.overrun,
+            remain: self.remain,
+            skip_for: self.skip_for,
+        }
+    }
+
+    fn skip(&mut self) -> ImageResult {
+        if 0 < self.skip_for {
+            self.buffer.seek(SeekFrom::Current(self.skip_for as i64))?;
+            self.skip_for = 0;
+        }
+        Ok(())
+    }
 }
 
 impl Chunk<'_> {
+    /// FourCC of the chunk (e.g. `VP8X`)
     pub fn identifier(&self) -> &[u8; 4] {
         &self.identifier
     }
+
+    /// Offset of the chunk (its identifier) in the image
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Size of the chunk data excluding the pad byte
+    pub fn size(&self) -> u32 {
+        self.size
+    }
 }
 
 impl Read for Chunk<'_> {
-    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+    fn read(&mut self, buf: &mut [u8]) -> crate::io::Result<usize> {
         self.buffer.read(buf).inspect(|&it| {
             *self.skip_for -= it;
         })
@@ -0,0 +1,324 @@
+//! Allocation-free views over in-memory images. The items borrow from the given slice.
+
+use alloc::format;
+
+use crate::errors::{Block, Corruption, CorruptionKind, ImageError, ImageResult};
+use crate::io;
+use crate::loader::{jpeg, png};
+use crate::types::{CommentSource, Format};
+
+const PNG_SIGNATURE: [u8; 8] = [0x89, 0x50, 0x4e, 0x47, 0x0d, 0x0a, 0x1a, 0x0a];
+const ICC_PROFILE: &[u8] = b"ICC_PROFILE\0";
+
+/// Chunk found by `png_chunks`
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct PngChunk<'a> {
+    pub data: &'a [u8],
+    pub name: [u8; 4],
+}
+
+/// Text of tEXt, zTXt or iTXt chunk
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct PngText<'a> {
+    /// `text` is zlib stream
+    pub compressed: bool,
+    /// Latin-1
+    pub keyword: &'a [u8],
+    /// Language tag of iTXt (empty for others)
+    pub language: &'a [u8],
+    pub source: CommentSource,
+    /// Latin-1 for tEXt and zTXt, UTF-8 for iTXt
+    pub text: &'a [u8],
+    /// UTF-8 keyword of iTXt (empty for others)
+    pub translated_keyword: &'a [u8],
+}
+
+/// Embedded ICC profile of PNG (iCCP)
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct IccProfile<'a> {
+    /// zlib stream of the profile
+    pub data: &'a [u8],
+    /// Latin-1
+    pub name: &'a [u8],
+}
+
+/// Segment found by `jpeg_segments`
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct JpegSegment<'a> {
+    /// Segment data excluding the length field
+    pub data: &'a [u8],
+    pub marker: u8,
+}
+
+/// Iterator over PNG chunks up to IEND
+pub struct PngChunks<'a> {
+    data: &'a [u8],
+    /// Offset of `data` in the image
+    offset: usize,
+}
+
+/// Iterator over JPEG segments before the first scan
+pub struct JpegSegments<'a> {
+    data: &'a [u8],
+    /// Offset of `data` in the image
+    offset: usize,
+}
+
+/// Returns the chunks of the PNG image in `data`
+pub fn png_chunks(data: &[u8]) -> ImageResult<PngChunks<'_>> {
+    match data.strip_prefix(&PNG_SIGNATURE[..]) {
+        Some(data) => Ok(PngChunks {
+            data,
+            offset: PNG_SIGNATURE.len(),
+        }),
+        None => Err(ImageError::InvalidSignature),
+    }
+}
+
+/// Returns the texts of tEXt, zTXt and iTXt chunks without decompression
+pub fn png_texts(data: &[u8]) -> ImageResult<impl Iterator<Item = ImageResult<PngText<'_>>>> {
+    Ok(png_chunks(data)?.filter_map(|chunk| {
+        chunk
+            .and_then(|chunk| match &chunk.name {
+                b"tEXt" => read_png_text(chunk.data).map(Some),
+                b"zTXt" => read_png_compressed_text(chunk.data).map(Some),
+                b"iTXt" => read_png_international_text(chunk.data).map(Some),
+                _ => Ok(None),
+            })
+            .transpose()
+    }))
+}
+
+/// Returns iCCP chunk without decompression
+pub fn png_icc_profile(data: &[u8]) -> ImageResult<Option<IccProfile<'_>>> {
+    for chunk in png_chunks(data)? {
+        let chunk = chunk?;
+        match &chunk.name {
+            b"iCCP" => {
+                let (name, rest) = split_null(chunk.data, *b"iCCP")?;
+                // The first byte is the compression method
+                return Ok(Some(IccProfile {
+                    data: rest.get(1..).unwrap_or_default(),
+                    name,
+                }));
+            }
+            // iCCP precedes IDAT
+            b"IDAT" => break,
+            _ => (),
+        }
+    }
+    Ok(None)
+}
+
+/// Returns the segments of the JPEG image in `data`
+pub fn jpeg_segments(data: &[u8]) -> ImageResult<JpegSegments<'_>> {
+    match data.strip_prefix(&[0xff, 0xd8][..]) {
+        Some(data) => Ok(JpegSegments { data, offset: 2 }),
+        None => Err(ImageError::InvalidSignature),
+    }
+}
+
+/// Returns the pieces of ICC profile in APP2 segments in order
+pub fn jpeg_icc_profile(data: &[u8]) -> ImageResult<impl Iterator<Item = ImageResult<&[u8]>>> {
+    Ok(jpeg_segments(data)?.filter_map(|segment| {
+        segment
+            .map(|segment| {
+                // 1 Sequence number
+                // 1 Number of chunks
+                segment
+                    .data
+                    .strip_prefix(ICC_PROFILE)
+                    .filter(|_| segment.marker == 0xe2)
+                    .map(|it| it.get(2..).unwrap_or_default())
+            })
+            .transpose()
+    }))
+}
+
+impl<'a> Iterator for PngChunks<'a> {
+    type Item = ImageResult<PngChunk<'a>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.data.is_empty() {
+            return None;
+        }
+
+        let result = read_png_chunk(self.data);
+        match result {
+            Ok((chunk, rest)) if chunk.name != *b"IEND" => {
+                self.offset += self.data.len() - rest.len();
+                self.data = rest;
+            }
+            _ => self.data = &[],
+        }
+        Some(
+            result
+                .map(|(chunk, _)| chunk)
+                .map_err(|err| with_offset(err, self.offset)),
+        )
+    }
+}
+
+impl<'a> Iterator for JpegSegments<'a> {
+    type Item = ImageResult<JpegSegment<'a>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.data.is_empty() {
+            return None;
+        }
+
+        match read_jpeg_segment(self.data) {
+            Ok(Some((segment, rest))) => {
+                self.offset += self.data.len() - rest.len();
+                self.data = rest;
+                Some(Ok(segment))
+            }
+            Ok(None) => {
+                self.data = &[];
+                None
+            }
+            Err(err) => {
+                self.data = &[];
+                Some(Err(with_offset(err, self.offset)))
+            }
+        }
+    }
+}
+
+fn read_png_chunk(data: &[u8]) -> ImageResult<(PngChunk<'_>, &[u8])> {
+    let mut rest = data;
+    let (length, name) = png::read_chunk_header(&mut rest)
+        .map_err(|err| or_truncated(err, || truncated(Format::Png, "chunk")))?;
+    let length = length as usize;
+    // 4 CRC
+    let end = length.checked_add(4).filter(|it| *it <= rest.len());
+    let end = end.ok_or_else(|| truncated_chunk(name))?;
+    Ok((
+        PngChunk {
+            data: &rest[..length],
+            name,
+        },
+        &rest[end..],
+    ))
+}
+
+fn read_png_text(data: &[u8]) -> ImageResult<PngText<'_>> {
+    let (keyword, text) = split_null(data, *b"tEXt")?;
+    Ok(PngText {
+        compressed: false,
+        keyword,
+        language: &[],
+        source: CommentSource::PngText,
+        text,
+        translated_keyword: &[],
+    })
+}
+
+fn read_png_compressed_text(data: &[u8]) -> ImageResult<PngText<'_>> {
+    let (keyword, rest) = split_null(data, *b"zTXt")?;
+    // The first byte is the compression method
+    Ok(PngText {
+        compressed: true,
+        keyword,
+        language: &[],
+        source: CommentSource::PngCompressedText,
+        text: rest.get(1..).unwrap_or_default(),
+        translated_keyword: &[],
+    })
+}
+
+fn read_png_international_text(data: &[u8]) -> ImageResult<PngText<'_>> {
+    const ITXT: [u8; 4] = *b"iTXt";
+
+    let (keyword, rest) = split_null(data, ITXT)?;
+    // 1 Compression flag
+    // 1 Compression method
+    let (compressed, rest) = match rest {
+        [flag, _, rest @ ..] => (*flag != 0, rest),
+        _ => return Err(truncated_chunk(ITXT)),
+    };
+    let (language, rest) = split_null(rest, ITXT)?;
+    let (translated_keyword, text) = split_null(rest, ITXT)?;
+    Ok(PngText {
+        compressed,
+        keyword,
+        language,
+        source: CommentSource::PngInternationalText,
+        text,
+        translated_keyword,
+    })
+}
+
+/// Returns the segment and the rest, or None at the first scan
+fn read_jpeg_segment(data: &[u8]) -> ImageResult<Option<(JpegSegment<'_>, &[u8])>> {
+    let mut rest = data;
+    let marker = jpeg::read_marker(&mut rest)
+        .map_err(|err| or_truncated(err, || truncated(Format::Jpeg, "segment")))?;
+
+    // SOS or EOI
+    if marker == 0xda || marker == 0xd9 {
+        return Ok(None);
+    }
+    if jpeg::is_stand_alone(marker) {
+        return Ok(Some((JpegSegment { data: &[], marker }, rest)));
+    }
+
+    let length = jpeg::read_length(&mut rest, marker)
+        .map_err(|err| or_truncated(err, || truncated_segment(marker)))?;
+    let length = usize::from(length);
+    if rest.len() < length {
+        return Err(truncated_segment(marker));
+    }
+    Ok(Some((
+        JpegSegment {
+            data: &rest[..length],
+            marker,
+        },
+        &rest[length..],
+    )))
+}
+
+/// Splits the null-terminated field of PNG chunk
+fn split_null(data: &[u8], chunk_name: [u8; 4]) -> ImageResult<(&[u8], &[u8])> {
+    let at = data
+        .iter()
+        .position(|it| *it == 0)
+        .ok_or_else(|| truncated_chunk(chunk_name))?;
+    Ok((&data[..at], &data[at + 1..]))
+}
+
+/// Replaces the end of the slice reported by the readers of the loaders with `f()`
+fn or_truncated<F: FnOnce() -> ImageError>(err: ImageError, f: F) -> ImageError {
+    match err {
+        ImageError::Io(err) if err.kind() == io::ErrorKind::UnexpectedEof => f(),
+        err => err,
+    }
+}
+
+fn truncated(format: Format, name: &'static str) -> ImageError {
+    Corruption::new(
+        format,
+        CorruptionKind::Truncated,
+        format!("Truncated {}", name),
+    )
+    .into()
+}
+
+fn truncated_chunk(name: [u8; 4]) -> ImageError {
+    Corruption::new(Format::Png, CorruptionKind::Truncated, "Truncated chunk")
+        .with_block(Block::Chunk(name))
+        .into()
+}
+
+fn truncated_segment(marker: u8) -> ImageError {
+    Corruption::new(Format::Jpeg, CorruptionKind::Truncated, "Truncated segment")
+        .with_block(Block::Marker(marker))
+        .into()
+}
+
+fn with_offset(err: ImageError, offset: usize) -> ImageError {
+    match err {
+        ImageError::CorruptImage(it) => it.with_offset(offset as u64).into(),
+        err => err,
+    }
+}
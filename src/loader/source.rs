@@ -0,0 +1,163 @@
+use alloc::vec;
+use alloc::vec::Vec;
+use core::ops::Range;
+#[cfg(feature = "std")]
+use std::fs::File;
+
+use crate::errors::ImageResult;
+use crate::io::{self, BufRead, Read, Seek, SeekFrom};
+use crate::loader::{load_with_format_and_options, require_format};
+use crate::types::{ImageMeta, LoadOptions};
+
+const DEFAULT_BLOCK_SIZE: usize = 4096;
+
+/// Random access source of bytes (e.g. HTTP range requests to object storage)
+pub trait ByteSource {
+    /// Returns up to `length` bytes at `offset`
+    ///
+    /// Fewer bytes mean the end of the source.
+    fn read_at(&mut self, offset: u64, length: usize) -> io::Result<Vec<u8>>;
+}
+
+/// Result of `probe`
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Probe {
+    pub meta: ImageMeta,
+    /// Byte ranges requested to the source in order
+    pub requests: Vec<Range<u64>>,
+}
+
+/// Reader over `ByteSource` fetching blocks on demand
+pub struct SourceReader<S: ByteSource> {
+    block: Vec<u8>,
+    block_offset: u64,
+    block_size: usize,
+    position: u64,
+    requests: Vec<Range<u64>>,
+    source: S,
+}
+
+/// Loads the image fetching only the blocks the loader reads
+pub fn probe<S: ByteSource>(source: S) -> ImageResult<Probe> {
+    probe_with_options(source, &LoadOptions::default())
+}
+
+pub fn probe_with_options<S: ByteSource>(source: S, options: &LoadOptions) -> ImageResult<Probe> {
+    let mut reader = SourceReader::new(source);
+    let format = require_format(&mut reader, options)?;
+    let meta = load_with_format_and_options(&mut reader, format, options)?;
+    Ok(Probe {
+        meta,
+        requests: reader.requests,
+    })
+}
+
+impl<S: ByteSource> SourceReader<S> {
+    pub fn new(source: S) -> Self {
+        Self::with_block_size(source, DEFAULT_BLOCK_SIZE)
+    }
+
+    /// `block_size` is the minimum length of each request
+    pub fn with_block_size(source: S, block_size: usize) -> Self {
+        SourceReader {
+            block: vec![],
+            block_offset: 0,
+            block_size: block_size.max(1),
+            position: 0,
+            requests: vec![],
+            source,
+        }
+    }
+
+    pub fn into_inner(self) -> S {
+        self.source
+    }
+
+    /// Returns the byte ranges requested to the source in order
+    pub fn requests(&self) -> &[Range<u64>] {
+        &self.requests
+    }
+
+    fn fetch(&mut self, length: usize) -> io::Result<()> {
+        let length = length.max(self.block_size);
+        self.block = self.source.read_at(self.position, length)?;
+        self.block_offset = self.position;
+        self.requests
+            .push(self.position..self.position + length as u64);
+        Ok(())
+    }
+
+    fn buffered(&self) -> &[u8] {
+        let block_end = self.block_offset + self.block.len() as u64;
+        if self.block_offset <= self.position && self.position < block_end {
+            &self.block[(self.position - self.block_offset) as usize..]
+        } else {
+            &[]
+        }
+    }
+}
+
+impl<S: ByteSource> Read for SourceReader<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.buffered().is_empty() {
+            // Fetch at once for the large reads
+            self.fetch(buf.len())?;
+        }
+        let available = self.buffered();
+        let n = available.len().min(buf.len());
+        buf[..n].copy_from_slice(&available[..n]);
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl<S: ByteSource> BufRead for SourceReader<S> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.buffered().is_empty() {
+            self.fetch(self.block_size)?;
+        }
+        Ok(self.buffered())
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.position += amt as u64;
+    }
+}
+
+impl<S: ByteSource> Seek for SourceReader<S> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(it) => Some(it),
+            SeekFrom::Current(it) => self.position.checked_add_signed(it),
+            SeekFrom::End(_) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "Length of source is unknown",
+                ))
+            }
+        };
+        self.position = position.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Invalid seek to a negative position",
+            )
+        })?;
+        Ok(self.position)
+    }
+}
+
+#[cfg(feature = "std")]
+impl ByteSource for File {
+    fn read_at(&mut self, offset: u64, length: usize) -> io::Result<Vec<u8>> {
+        let mut result = vec![];
+        self.seek(SeekFrom::Start(offset))?;
+        self.take(length as u64).read_to_end(&mut result)?;
+        Ok(result)
+    }
+}
+
+impl<S: ?Sized + ByteSource> ByteSource for &mut S {
+    fn read_at(&mut self, offset: u64, length: usize) -> io::Result<Vec<u8>> {
+        (**self).read_at(offset, length)
+    }
+}
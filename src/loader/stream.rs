@@ -0,0 +1,428 @@
+use alloc::vec;
+use alloc::vec::Vec;
+use core::ops::Range;
+
+use crate::errors::{Corruption, CorruptionKind, ImageResult};
+use crate::io::{self, BufRead, Read, Seek, SeekFrom};
+use crate::loader::limits::{resume, Usage};
+use crate::loader::resumable::Resumable;
+use crate::loader::{
+    detect_format_with_options, gif, jpeg, load_with_format_and_options, png, registry,
+    unsupported, webp, SIGNATURE_LENGTH,
+};
+use crate::types::{Dimensions, Format, ImageMeta, LoadOptions};
+
+/// Push-based loader for images arriving in chunks (e.g. chunked HTTP bodies)
+///
+/// GIF, JPEG, PNG and WebP are loaded block by block, and the loader is suspended after the last
+/// block completed. When a block needs more bytes, it is retried from there once the bytes fed
+/// since reach the bytes it has read, so each byte is read a bounded number of times. The other
+/// formats are retried from the start in the same way.
+///
+/// Only the bytes the loader has actually read are retained, so the data skipped over (e.g. PNG
+/// IDAT, GIF image data) is dropped as soon as it is fed.
+pub struct StreamLoader {
+    /// Loader suspended after the last block completed
+    checkpoint: Option<Checkpoint>,
+    format: Option<Format>,
+    /// Total length of the fed bytes
+    length: u64,
+    meta: Option<ImageMeta>,
+    options: LoadOptions,
+    /// Retained bytes sorted by offset. The last one receives the bytes to be fed.
+    segments: Vec<Segment>,
+    /// Length required before the next attempt
+    watermark: u64,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Status {
+    NeedMoreData,
+    Done(ImageMeta),
+}
+
+/// Loader suspended at `offset`, with the usage of the limits so far
+#[derive(Clone)]
+struct Checkpoint {
+    offset: u64,
+    reader: Suspended,
+    usage: Usage,
+}
+
+#[derive(Clone)]
+enum Suspended {
+    Gif(gif::MetaReader),
+    Jpeg(jpeg::MetaReader),
+    Png(png::MetaReader),
+    Webp(webp::MetaReader),
+}
+
+/// Result of a step of the loader
+enum Progress {
+    Done(ImageMeta),
+    Suspended(Checkpoint),
+}
+
+struct Segment {
+    data: Vec<u8>,
+    offset: u64,
+}
+
+/// Reader over the retained bytes of `StreamLoader`
+struct Replay<'a> {
+    /// Offset of the first byte missing, if the loader tried to read it
+    exhausted_at: Option<u64>,
+    /// At the end of the stream, missing bytes are reported as EOF
+    finished: bool,
+    length: u64,
+    position: u64,
+    read: Vec<Range<u64>>,
+    segments: &'a [Segment],
+}
+
+impl StreamLoader {
+    pub fn new() -> Self {
+        Self::with_options(LoadOptions::default())
+    }
+
+    pub fn with_options(options: LoadOptions) -> Self {
+        StreamLoader {
+            checkpoint: None,
+            format: None,
+            length: 0,
+            meta: None,
+            options,
+            segments: vec![Segment {
+                data: vec![],
+                offset: 0,
+            }],
+            watermark: 1,
+        }
+    }
+
+    /// Returns the detected format
+    pub fn format(&self) -> Option<Format> {
+        self.format
+    }
+
+    /// Returns the dimensions as soon as the header is loaded
+    ///
+    /// PNG and GIF are `Done` at the first IDAT and the trailer respectively, which may arrive
+    /// long after the header.
+    pub fn dimensions(&self) -> Option<Dimensions> {
+        match (&self.meta, &self.checkpoint) {
+            (Some(meta), _) => Some(meta.dimensions.clone()),
+            (None, Some(checkpoint)) => checkpoint.reader.dimensions().cloned(),
+            (None, None) => None,
+        }
+    }
+
+    /// Appends the next chunk of the image and tries to load it
+    pub fn feed(&mut self, data: &[u8]) -> ImageResult<Status> {
+        if let Some(meta) = self.meta.as_ref() {
+            return Ok(Status::Done(meta.clone()));
+        }
+
+        self.append(data);
+        if self.length < self.watermark {
+            return Ok(Status::NeedMoreData);
+        }
+        self.attempt(false)
+    }
+
+    /// Tells the end of the image, and returns the result of the final attempt
+    pub fn finish(mut self) -> ImageResult<ImageMeta> {
+        if let Some(meta) = self.meta.take() {
+            return Ok(meta);
+        }
+
+        match (self.attempt(true)?, self.format) {
+            (Status::Done(meta), _) => Ok(meta),
+            (Status::NeedMoreData, Some(format)) => {
+                Err(
+                    Corruption::new(format, CorruptionKind::Truncated, "Unexpected end of image")
+                        .with_offset(self.length)
+                        .into(),
+                )
+            }
+            (Status::NeedMoreData, None) => Err(unsupported(&self.segments[0].data, &self.options)),
+        }
+    }
+
+    /// Returns the number of the next bytes the loader skips over
+    ///
+    /// The source may seek over them and call `skip` instead of feeding them.
+    pub fn skippable(&self) -> u64 {
+        self.segments
+            .last()
+            .map_or(0, |it| it.offset.saturating_sub(self.length))
+    }
+
+    /// Advances the stream without feeding data (See `skippable`)
+    pub fn skip(&mut self, length: u64) -> ImageResult {
+        if self.skippable() < length {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "Skipping needed data").into());
+        }
+        self.length += length;
+        Ok(())
+    }
+
+    fn append(&mut self, data: &[u8]) {
+        let tail = self
+            .segments
+            .last_mut()
+            .expect("The tail segment always exists");
+        // The bytes before the tail are skipped by the loader
+        let skip = tail.offset.saturating_sub(self.length);
+        if let Some(data) = usize::try_from(skip).ok().and_then(|it| data.get(it..)) {
+            tail.data.extend_from_slice(data);
+        }
+        self.length += data.len() as u64;
+    }
+
+    fn attempt(&mut self, finished: bool) -> ImageResult<Status> {
+        let format = match self.format {
+            Some(format) => format,
+            None => {
+                let head = &self.segments[0].data;
+                match detect_format_with_options(head, &self.options) {
+                    Some(format) => *self.format.insert(format),
+                    None if !finished && head.len() < SIGNATURE_LENGTH => {
+                        self.watermark = self.length + 1;
+                        return Ok(Status::NeedMoreData);
+                    }
+                    None => return Err(unsupported(head, &self.options)),
+                }
+            }
+        };
+
+        loop {
+            let offset = self.checkpoint.as_ref().map_or(0, |it| it.offset);
+            let mut replay = Replay {
+                exhausted_at: None,
+                finished,
+                length: self.length,
+                position: offset,
+                read: vec![],
+                segments: &self.segments,
+            };
+            let result = step(self.checkpoint.as_ref(), &mut replay, format, &self.options);
+
+            if let Some(exhausted_at) = replay.exhausted_at {
+                // Retry the step when it can read as many new bytes as it has read
+                let read = replay.read;
+                let length: u64 = read.iter().map(|it| it.end - it.start).sum();
+                self.retain(read, exhausted_at);
+                self.watermark = exhausted_at + length.max(1);
+                return Ok(Status::NeedMoreData);
+            }
+            match result? {
+                Progress::Done(meta) => {
+                    self.checkpoint = None;
+                    self.segments.clear();
+                    self.meta = Some(meta.clone());
+                    return Ok(Status::Done(meta));
+                }
+                Progress::Suspended(checkpoint) => self.checkpoint = Some(checkpoint),
+            }
+        }
+    }
+
+    /// Drops the bytes except for `read` ranges and the bytes from `tail`
+    fn retain(&mut self, mut read: Vec<Range<u64>>, tail: u64) {
+        read.sort_by_key(|it| it.start);
+
+        let mut merged: Vec<Range<u64>> = vec![];
+        for range in read {
+            match merged.last_mut() {
+                Some(last) if range.start <= last.end => last.end = last.end.max(range.end),
+                _ => merged.push(range),
+            }
+        }
+
+        let mut segments = Vec::with_capacity(merged.len() + 1);
+        for range in merged {
+            let mut data = Vec::with_capacity((range.end - range.start) as usize);
+            let first = self
+                .segments
+                .partition_point(|it| it.offset + it.data.len() as u64 <= range.start);
+            for segment in &self.segments[first..] {
+                if range.end <= segment.offset {
+                    break;
+                }
+                let end = segment.offset + segment.data.len() as u64;
+                let from = (range.start.max(segment.offset) - segment.offset) as usize;
+                let to = (range.end.min(end) - segment.offset) as usize;
+                data.extend_from_slice(&segment.data[from..to]);
+            }
+            segments.push(Segment {
+                data,
+                offset: range.start,
+            });
+        }
+
+        // The missing bytes are at the end, so the tail has no bytes yet
+        segments.push(Segment {
+            data: vec![],
+            offset: tail,
+        });
+        self.segments = segments;
+    }
+}
+
+/// Reads the header or the next block from `checkpoint`
+///
+/// The formats not loaded block by block, including the ones the registry of `options` overrides,
+/// are loaded at once.
+fn step(
+    checkpoint: Option<&Checkpoint>,
+    image: &mut Replay,
+    format: Format,
+    options: &LoadOptions,
+) -> ImageResult<Progress> {
+    let Some(checkpoint) = checkpoint else {
+        if !registry::loaders(options).is_builtin(format) {
+            return load_with_format_and_options(image, format, options).map(Progress::Done);
+        }
+        let mut usage = Usage::default();
+        let reader = match format {
+            Format::Gif => Suspended::Gif(open(image, format, options, &mut usage)?),
+            Format::Jpeg => Suspended::Jpeg(open(image, format, options, &mut usage)?),
+            Format::Png => Suspended::Png(open(image, format, options, &mut usage)?),
+            Format::Webp => Suspended::Webp(open(image, format, options, &mut usage)?),
+            _ => return load_with_format_and_options(image, format, options).map(Progress::Done),
+        };
+        return Ok(Progress::Suspended(Checkpoint {
+            offset: image.position,
+            reader,
+            usage,
+        }));
+    };
+
+    let mut checkpoint = checkpoint.clone();
+    let usage = &mut checkpoint.usage;
+    let meta = match &mut checkpoint.reader {
+        Suspended::Gif(it) => resume_block(it, image, format, options, usage)?,
+        Suspended::Jpeg(it) => resume_block(it, image, format, options, usage)?,
+        Suspended::Png(it) => resume_block(it, image, format, options, usage)?,
+        Suspended::Webp(it) => resume_block(it, image, format, options, usage)?,
+    };
+    match meta {
+        Some(meta) => Ok(Progress::Done(meta)),
+        None => {
+            checkpoint.offset = image.position;
+            Ok(Progress::Suspended(checkpoint))
+        }
+    }
+}
+
+fn open<T: Resumable>(
+    image: &mut Replay,
+    format: Format,
+    options: &LoadOptions,
+    usage: &mut Usage,
+) -> ImageResult<T> {
+    resume(image, format, options, usage, |image, cx| {
+        T::open(image, cx)
+    })
+}
+
+/// Reads the next block, and returns the meta at the end
+fn resume_block<T: Resumable>(
+    reader: &mut T,
+    image: &mut Replay,
+    format: Format,
+    options: &LoadOptions,
+    usage: &mut Usage,
+) -> ImageResult<Option<ImageMeta>> {
+    match resume(image, format, options, usage, |image, cx| {
+        reader.step(image, cx)
+    }) {
+        Ok(false) => Ok(None),
+        Ok(true) => reader.clone().finish(Ok(())).map(Some),
+        Err(err) => reader.clone().finish(Err(err)).map(Some),
+    }
+}
+
+impl Suspended {
+    fn dimensions(&self) -> Option<&Dimensions> {
+        match self {
+            Suspended::Gif(it) => it.dimensions(),
+            Suspended::Jpeg(it) => it.dimensions(),
+            Suspended::Png(it) => it.dimensions(),
+            Suspended::Webp(it) => it.dimensions(),
+        }
+    }
+}
+
+impl Default for StreamLoader {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Read for Replay<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let n = available.len().min(buf.len());
+        buf[..n].copy_from_slice(&available[..n]);
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl BufRead for Replay<'_> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.length <= self.position {
+            if self.finished {
+                return Ok(&[]);
+            }
+            self.exhausted_at.get_or_insert(self.position);
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "Need more data",
+            ));
+        }
+
+        let position = self.position;
+        let index = self
+            .segments
+            .partition_point(|it| it.offset + it.data.len() as u64 <= position);
+        self.segments
+            .get(index)
+            .filter(|it| it.offset <= position)
+            .map(|it| &it.data[(position - it.offset) as usize..])
+            .ok_or_else(|| io::Error::other("Dropped data"))
+    }
+
+    fn consume(&mut self, amt: usize) {
+        let end = self.position + amt as u64;
+        match self.read.last_mut() {
+            Some(last) if last.end == self.position => last.end = end,
+            _ => self.read.push(self.position..end),
+        }
+        self.position = end;
+    }
+}
+
+impl Seek for Replay<'_> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(it) => Some(it),
+            SeekFrom::Current(it) => self.position.checked_add_signed(it),
+            SeekFrom::End(_) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "Length of stream is unknown",
+                ))
+            }
+        };
+        self.position = position.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Invalid seek to a negative position",
+            )
+        })?;
+        Ok(self.position)
+    }
+}
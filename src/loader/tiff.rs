@@ -0,0 +1,132 @@
+use alloc::borrow::Cow;
+use alloc::format;
+use alloc::vec::Vec;
+
+use crate::errors::{Corruption, CorruptionKind, ImageResult};
+use crate::io::Read;
+use crate::types::Format;
+
+// See: https://www.itu.int/itudoc/itu-t/com16/tiff-fx/docs/tiff6.pdf
+
+/// TIFF structure embedded in other containers (EXIF APP1, MPF APP2)
+pub struct Tiff<'a> {
+    data: &'a [u8],
+    little_endian: bool,
+}
+
+pub struct Entry {
+    pub tag: u16,
+    pub field_type: u16,
+    pub count: u32,
+    /// Raw value/offset field
+    value: [u8; 4],
+}
+
+impl<'a> Tiff<'a> {
+    pub fn new(data: &'a [u8]) -> ImageResult<Self> {
+        let little_endian = match data.get(0..4) {
+            Some([0x49, 0x49, 0x2a, 0x00]) => true,
+            Some([0x4d, 0x4d, 0x00, 0x2a]) => false,
+            _ => return Err(corrupt(CorruptionKind::InvalidField, "Invalid TIFF header").into()),
+        };
+        Ok(Tiff {
+            data,
+            little_endian,
+        })
+    }
+
+    pub fn first_ifd(&self) -> ImageResult<u32> {
+        self.u32_at(4)
+    }
+
+    /// Returns the entries and the offset of the next IFD (0 means none)
+    pub fn read_ifd(&self, offset: u32) -> ImageResult<(Vec<Entry>, u32)> {
+        let offset = offset as usize;
+        let count = self.u16_at(offset)? as usize;
+        let mut entries = Vec::with_capacity(count);
+        for i in 0..count {
+            let at = offset + 2 + i * 12;
+            let value = self.slice(at + 8, 4)?;
+            entries.push(Entry {
+                tag: self.u16_at(at)?,
+                field_type: self.u16_at(at + 2)?,
+                count: self.u32_at(at + 4)?,
+                value: [value[0], value[1], value[2], value[3]],
+            });
+        }
+        let next = self.u32_at(offset + 2 + count * 12)?;
+        Ok((entries, next))
+    }
+
+    /// Returns the value of SHORT or LONG entry
+    pub fn value_u32(&self, entry: &Entry) -> Option<u32> {
+        match entry.field_type {
+            3 => Some(u32::from(self.u16(&entry.value[0..2]))),
+            4 => Some(self.u32(&entry.value)),
+            _ => None,
+        }
+    }
+
+    /// Returns the bytes of UNDEFINED or BYTE entry
+    pub fn value_bytes<'b>(&self, entry: &'b Entry) -> ImageResult<&'b [u8]>
+    where
+        'a: 'b,
+    {
+        if entry.count <= 4 {
+            // Small values are stored in the entry itself
+            return Ok(&entry.value[0..entry.count as usize]);
+        }
+        self.slice(self.u32(&entry.value) as usize, entry.count as usize)
+    }
+
+    /// Reads u32 in the byte order of this TIFF
+    pub fn read_u32<R: Read>(&self, reader: &mut R) -> ImageResult<u32> {
+        let mut bytes = [0u8; 4];
+        reader.read_exact(&mut bytes)?;
+        Ok(self.u32(&bytes))
+    }
+
+    pub fn u16_at(&self, offset: usize) -> ImageResult<u16> {
+        self.slice(offset, 2).map(|it| self.u16(it))
+    }
+
+    pub fn u32_at(&self, offset: usize) -> ImageResult<u32> {
+        self.slice(offset, 4).map(|it| self.u32(it))
+    }
+
+    fn u16(&self, bytes: &[u8]) -> u16 {
+        let bytes = [bytes[0], bytes[1]];
+        if self.little_endian {
+            u16::from_le_bytes(bytes)
+        } else {
+            u16::from_be_bytes(bytes)
+        }
+    }
+
+    fn u32(&self, bytes: &[u8]) -> u32 {
+        let bytes = [bytes[0], bytes[1], bytes[2], bytes[3]];
+        if self.little_endian {
+            u32::from_le_bytes(bytes)
+        } else {
+            u32::from_be_bytes(bytes)
+        }
+    }
+
+    fn slice(&self, offset: usize, length: usize) -> ImageResult<&'a [u8]> {
+        offset
+            .checked_add(length)
+            .and_then(|end| self.data.get(offset..end))
+            .ok_or_else(|| {
+                corrupt(
+                    CorruptionKind::InvalidField,
+                    format!("TIFF offset out of bounds: {}", offset),
+                )
+                .into()
+            })
+    }
+}
+
+/// TIFF is embedded only in JPEG for now
+fn corrupt<T: Into<Cow<'static, str>>>(kind: CorruptionKind, message: T) -> Corruption {
+    Corruption::new(Format::Jpeg, kind, message)
+}
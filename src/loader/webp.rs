@@ -1,10 +1,14 @@
-use std::io::{BufRead, Read, Seek};
+use alloc::borrow::Cow;
+use alloc::format;
 
-use byteorder::{LittleEndian, ReadBytesExt};
+use byteorder::LittleEndian;
 
-use crate::errors::{ImageError, ImageResult};
-use crate::loader::riff::{Chunk, RiffReader};
-use crate::types::{Color, ColorMode, Dimensions, Format, ImageMeta};
+use crate::errors::{Block, Corruption, CorruptionKind, ImageError, ImageResult};
+use crate::io::{BufRead, Read, ReadExt, Seek};
+use crate::loader::limits::{complete, limited, Context};
+use crate::loader::resumable::{run, Resumable};
+use crate::loader::riff::{Chunk, RiffReader, RiffState};
+use crate::types::{Color, ColorMode, Dimensions, Format, ImageMeta, LoadOptions};
 
 pub struct WebpReader<T: BufRead + Seek> {
     animation_frames: usize,
@@ -12,29 +16,24 @@ pub struct WebpReader<T: BufRead + Seek> {
     riff: RiffReader<T>,
 }
 
+/// `WebpReader` apart from the image, suspended between the chunks
+#[derive(Clone)]
+pub(crate) struct MetaReader {
+    animation_frames: usize,
+    dimensions: Option<Dimensions>,
+    riff: RiffState,
+}
+
 pub fn load<R: ?Sized + BufRead + Seek>(image: &mut R) -> ImageResult<ImageMeta> {
-    let mut reader = WebpReader::new(RiffReader::open(image)?);
-    reader.read()?;
-
-    let dimensions = reader
-        .dimensions
-        .ok_or_else(|| ImageError::CorruptImage("VP8? chunk not found".into()))?;
-    let animation_frames = if 0 < reader.animation_frames {
-        Some(reader.animation_frames)
-    } else {
-        None
-    };
-    let color = Color {
-        alpha_channel: true,
-        mode: ColorMode::Rgb,
-        resolution: 8,
-    };
-
-    Ok(ImageMeta {
-        animation_frames,
-        color,
-        dimensions,
-        format: Format::Webp,
+    load_with_options(image, &LoadOptions::default())
+}
+
+pub fn load_with_options<R: ?Sized + BufRead + Seek>(
+    image: &mut R,
+    options: &LoadOptions,
+) -> ImageResult<ImageMeta> {
+    limited(image, Format::Webp, options, |image, cx| {
+        run::<MetaReader, _>(image, cx)
     })
 }
 
@@ -47,24 +46,119 @@ impl<T: BufRead + Seek> WebpReader<T> {
         }
     }
 
-    fn read(&mut self) -> ImageResult {
-        if self.riff.form_type() != b"WEBP" {
-            return Err(ImageError::InvalidSignature);
+    fn resume(buffer: T, state: MetaReader) -> Self {
+        Self {
+            animation_frames: state.animation_frames,
+            dimensions: state.dimensions,
+            riff: RiffReader::resume(buffer, state.riff),
         }
+    }
+
+    fn suspend(self) -> MetaReader {
+        MetaReader {
+            animation_frames: self.animation_frames,
+            dimensions: self.dimensions,
+            riff: self.riff.suspend(),
+        }
+    }
 
-        while let Some(mut chunk) = self.riff.read_chunk()? {
-            match chunk.identifier() {
-                b"ANMF" => self.animation_frames += 1,
-                b"VP8 " => self.dimensions = Some(read_vp8_chunk(&mut chunk)?),
-                b"VP8L" => self.dimensions = Some(read_vp8l_chunk(&mut chunk)?),
-                b"VP8X" => self.dimensions = Some(read_vp8x_chunk(&mut chunk)?),
-                _ => (),
+    /// Reads the next chunk, and returns true at the end
+    fn read_chunk(&mut self, cx: &mut Context) -> ImageResult<bool> {
+        if cx.is_lenient() && self.riff.is_at_end()? {
+            // The image is shorter than the RIFF header tells
+            return Ok(true);
+        }
+        let Some(mut chunk) = self.riff.read_chunk()? else {
+            self.verify_end(cx)?;
+            return Ok(true);
+        };
+        cx.block()?;
+        let dimensions = match chunk.identifier() {
+            b"ANMF" => {
+                self.animation_frames += 1;
+                None
+            }
+            b"VP8 " => Some(read_vp8_chunk(&mut chunk)?),
+            b"VP8L" => Some(read_vp8l_chunk(&mut chunk)?),
+            b"VP8X" => Some(read_vp8x_chunk(&mut chunk)?),
+            _ => None,
+        };
+        if let Some(dimensions) = dimensions {
+            cx.dimensions(&dimensions)?;
+            self.dimensions = Some(dimensions);
+        }
+        Ok(false)
+    }
+
+    fn verify_end(&mut self, cx: &Context) -> ImageResult {
+        if cx.is_strict() {
+            if self.riff.is_overrun() {
+                return Err(
+                    corrupt(CorruptionKind::InvalidField, "Chunk exceeds RIFF size").into(),
+                );
+            }
+            if !self.riff.is_at_end()? {
+                return Err(
+                    corrupt(CorruptionKind::UnexpectedBlock, "Trailing data after RIFF").into(),
+                );
             }
         }
         Ok(())
     }
 }
 
+impl Resumable for MetaReader {
+    fn open<R: ?Sized + BufRead + Seek>(image: &mut R, _: &mut Context) -> ImageResult<Self> {
+        let reader = WebpReader::new(RiffReader::open(image)?);
+        if reader.riff.form_type() != b"WEBP" {
+            return Err(ImageError::InvalidSignature);
+        }
+        Ok(reader.suspend())
+    }
+
+    fn step<R: ?Sized + BufRead + Seek>(
+        &mut self,
+        image: &mut R,
+        cx: &mut Context,
+    ) -> ImageResult<bool> {
+        let mut reader = WebpReader::resume(image, self.clone());
+        let result = reader.read_chunk(cx);
+        *self = reader.suspend();
+        result
+    }
+
+    fn dimensions(&self) -> Option<&Dimensions> {
+        self.dimensions.as_ref()
+    }
+
+    fn finish(self, result: ImageResult) -> ImageResult<ImageMeta> {
+        // VP8X precedes the frames of animation
+        let Some(dimensions) = self.dimensions else {
+            result?;
+            return Err(corrupt(CorruptionKind::Truncated, "VP8? chunk not found").into());
+        };
+        let animation_frames = if 0 < self.animation_frames {
+            Some(self.animation_frames)
+        } else {
+            None
+        };
+        let color = Color {
+            alpha_channel: true,
+            mode: ColorMode::Rgb,
+            resolution: 8,
+        };
+
+        let meta = ImageMeta {
+            animation_frames,
+            color,
+            color_space: None,
+            dimensions,
+            format: Format::Webp,
+        };
+        complete(result, meta)
+    }
+}
+
 fn read_vp8_chunk(chunk: &mut Chunk) -> ImageResult<Dimensions> {
     // See https://tools.ietf.org/html/rfc6386#page-30
 
@@ -76,9 +170,12 @@ fn read_vp8_chunk(chunk: &mut Chunk) -> ImageResult<Dimensions> {
         let mut signature = [0u8; 3];
         chunk.read_exact(&mut signature)?;
         if signature != [0x9d, 0x01, 0x2a] {
-            return Err(ImageError::CorruptImage(
-                format!("Invalid key frame code: {:?}", signature).into(),
-            ));
+            return Err(corrupt(
+                CorruptionKind::InvalidField,
+                format!("Invalid key frame code: {:?}", signature),
+            )
+            .with_block(Block::Chunk(*b"VP8 "))
+            .into());
         }
 
         let mut bits = [0u8; 2];
@@ -93,7 +190,9 @@ fn read_vp8_chunk(chunk: &mut Chunk) -> ImageResult<Dimensions> {
         });
     }
 
-    Err(ImageError::CorruptImage("Not key frame".into()))
+    Err(corrupt(CorruptionKind::InvalidField, "Not key frame")
+        .with_block(Block::Chunk(*b"VP8 "))
+        .into())
 }
 
 fn read_vp8l_chunk(chunk: &mut Chunk) -> ImageResult<Dimensions> {
@@ -101,9 +200,12 @@ fn read_vp8l_chunk(chunk: &mut Chunk) -> ImageResult<Dimensions> {
 
     let signature = chunk.read_u8()?;
     if signature != 0x2f {
-        return Err(ImageError::CorruptImage(
-            format!("Invalid signature: 0x{:x}", signature).into(),
-        ));
+        return Err(corrupt(
+            CorruptionKind::InvalidField,
+            format!("Invalid signature: 0x{:x}", signature),
+        )
+        .with_block(Block::Chunk(*b"VP8L"))
+        .into());
     }
 
     let mut bits = [0u8; 4];
@@ -140,3 +242,7 @@ fn extract_dimension(bits: [u8; 2]) -> (u16, u8) {
     let scale = (bits[1] & 0b1100_0000) >> 6;
     (size, scale)
 }
+
+fn corrupt<T: Into<Cow<'static, str>>>(kind: CorruptionKind, message: T) -> Corruption {
+    Corruption::new(Format::Webp, kind, message)
+}
@@ -1,7 +1,19 @@
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+use core::ops::Range;
+
+use crate::errors::ImageResult;
+use crate::io::{Read, Seek, SeekFrom};
+use crate::loader::registry::Registry;
+
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct ImageMeta {
     pub animation_frames: Option<usize>,
     pub color: Color,
+    pub color_space: Option<ColorSpace>,
     pub dimensions: Dimensions,
     pub format: Format,
 }
@@ -12,7 +24,7 @@ pub struct Dimensions {
     pub width: u32,
 }
 
-#[derive(Clone, Copy, Debug, Eq, PartialEq, strum::Display)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub enum Format {
     Bmp,
     Gif,
@@ -21,6 +33,8 @@ pub enum Format {
     Webp,
     Hdr,
     Qoi,
+    /// Format of a loader registered by the application (See `registry::FormatLoader`)
+    Custom(&'static str),
 }
 
 #[derive(Clone, Copy, Debug, Eq, PartialEq, strum::Display)]
@@ -37,8 +51,410 @@ pub struct Color {
     pub resolution: u8,
 }
 
+/// Color table of an indexed image
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Palette {
+    pub entries: Vec<PaletteEntry>,
+    pub source: PaletteSource,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct PaletteEntry {
+    /// 255 means opaque
+    pub alpha: u8,
+    pub blue: u8,
+    pub green: u8,
+    pub red: u8,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, strum::Display)]
+pub enum PaletteSource {
+    /// Color table of BMP
+    Bmp,
+    /// Global color table of GIF
+    GifGlobal,
+    /// Local color table of the frame (0-origin) of GIF
+    GifLocal(usize),
+    /// Global color table of GIF with the transparent color of the frame (0-origin)
+    GifFrame(usize),
+    /// PLTE and tRNS of PNG
+    Png,
+}
+
+/// Human-readable text stored in the image
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Comment {
+    /// Keyword of PNG text chunks (e.g. `Comment`, `Author`)
+    pub keyword: Option<String>,
+    pub source: CommentSource,
+    pub text: String,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, strum::Display)]
+pub enum CommentSource {
+    /// Comment extension of GIF
+    Gif,
+    /// `#` line in the information header of HDR
+    Hdr,
+    /// COM segment of JPEG
+    Jpeg,
+    /// iTXt chunk of PNG
+    PngInternationalText,
+    /// tEXt chunk of PNG
+    PngText,
+    /// zTXt chunk of PNG
+    PngCompressedText,
+}
+
+/// Color space signaling of the image
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct ColorSpace {
+    pub chromaticities: Option<Chromaticities>,
+    pub cicp: Option<Cicp>,
+    pub content_light_level: Option<ContentLightLevel>,
+    /// Gamma times 100000 (e.g. 45455 for 1/2.2)
+    pub gamma: Option<u32>,
+    pub mastering_display: Option<MasteringDisplay>,
+    /// Rendering intent of sRGB
+    pub rendering_intent: Option<RenderingIntent>,
+    /// Transfer function resolved from the other fields
+    pub transfer: Option<Transfer>,
+}
+
+/// White point and primaries in CIE 1931 xy, times 100000
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Chromaticities {
+    pub blue: [u32; 2],
+    pub green: [u32; 2],
+    pub red: [u32; 2],
+    pub white: [u32; 2],
+}
+
+/// Coding-independent code points (ITU-T H.273)
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Cicp {
+    pub color_primaries: u8,
+    pub full_range: bool,
+    pub matrix_coefficients: u8,
+    pub transfer_characteristics: u8,
+}
+
+/// Values in 0.0001 cd/m²
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ContentLightLevel {
+    pub max_content_light_level: u32,
+    pub max_frame_average_light_level: u32,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct MasteringDisplay {
+    /// Luminance in 0.0001 cd/m²
+    pub max_luminance: u32,
+    pub min_luminance: u32,
+    /// Red, green and blue in CIE 1931 xy, times 50000
+    pub primaries: [[u16; 2]; 3],
+    pub white_point: [u16; 2],
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, strum::Display)]
+pub enum RenderingIntent {
+    AbsoluteColorimetric,
+    Perceptual,
+    RelativeColorimetric,
+    Saturation,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, strum::Display)]
+pub enum Transfer {
+    /// ITU-R BT.709 (and BT.601, BT.2020 SDR)
+    Bt709,
+    /// Power function given by gamma times 100000
+    Gamma(u32),
+    /// Hybrid Log-Gamma
+    Hlg,
+    Linear,
+    /// Perceptual Quantizer (SMPTE ST 2084)
+    Pq,
+    Srgb,
+    /// Other transfer characteristics of H.273
+    Unknown(u8),
+}
+
+/// Images stored in a Multi-Picture Format JPEG
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct MultiPicture {
+    /// The primary image declares an HDR gain map in XMP (`hdrgm` namespace)
+    pub gain_map: bool,
+    pub images: Vec<MultiPictureImage>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct MultiPictureImage {
+    /// The image is an HDR gain map (Ultra HDR / Adobe gain map)
+    pub gain_map: bool,
+    pub kind: MultiPictureKind,
+    /// Byte range of the image in the file
+    pub range: Range<u64>,
+    pub representative: bool,
+}
+
+/// MP Type Code
+#[derive(Clone, Copy, Debug, Eq, PartialEq, strum::Display)]
+pub enum MultiPictureKind {
+    Baseline,
+    Disparity,
+    LargeThumbnailFullHd,
+    LargeThumbnailVga,
+    MultiAngle,
+    Panorama,
+    /// Used for gain maps
+    Undefined,
+    Unknown(u32),
+}
+
+/// Embedded preview image
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Thumbnail {
+    pub dimensions: Option<Dimensions>,
+    pub encoding: ThumbnailEncoding,
+    /// Byte range of the thumbnail data in the file
+    pub range: Range<u64>,
+    pub source: ThumbnailSource,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, strum::Display)]
+pub enum ThumbnailEncoding {
+    /// 8-bit palette indices preceded by a 768 bytes RGB palette
+    Indexed,
+    Jpeg,
+    /// Packed 24-bit RGB pixels
+    Rgb,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, strum::Display)]
+pub enum ThumbnailSource {
+    /// IFD1 of EXIF (APP1)
+    Exif,
+    /// JFIF (APP0)
+    Jfif,
+    /// JFIF extension (APP0)
+    Jfxx,
+    /// Image resource block of Photoshop (APP13)
+    Photoshop,
+}
+
+/// Resource limits for loading untrusted images
+///
+/// The loaders fail with `CorruptionKind::LimitExceeded` instead of going beyond them.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Limits {
+    /// Maximum total bytes allocated for the data read from the image (e.g. texts, segments)
+    pub max_alloc: usize,
+    /// Maximum number of chunks, segments, blocks and frames visited
+    pub max_blocks: usize,
+    /// Maximum bytes read from the image, excluding the bytes skipped by seeking
+    pub max_bytes_read: u64,
+    pub max_dimensions: Dimensions,
+    /// Maximum length of a text header line (HDR)
+    pub max_line_length: usize,
+}
+
+/// Limit exceeded, named after the field of `Limits`
+#[derive(Clone, Copy, Debug, Eq, PartialEq, strum::Display)]
+pub enum Limit {
+    #[strum(serialize = "max_alloc")]
+    Alloc,
+    #[strum(serialize = "max_blocks")]
+    Blocks,
+    #[strum(serialize = "max_bytes_read")]
+    BytesRead,
+    #[strum(serialize = "max_dimensions")]
+    Dimensions,
+    #[strum(serialize = "max_line_length")]
+    LineLength,
+}
+
+/// Options of the loaders
+#[derive(Clone, Debug, Default)]
+pub struct LoadOptions {
+    pub limits: Limits,
+    /// Loaders used instead of the built-in ones, if any (compared by identity)
+    pub registry: Option<Arc<Registry>>,
+    pub strictness: Strictness,
+}
+
+/// How strictly the loaders follow the format specifications
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, strum::Display)]
+pub enum Strictness {
+    /// Tolerates unknown GIF extensions, trailing garbage and missing end of image
+    Lenient,
+    /// Checks what is needed to read the metadata
+    #[default]
+    Normal,
+    /// Also verifies PNG CRCs, chunk ordering, GIF block terminators, JPEG marker sequence,
+    /// RIFF sizes and trailing data, reading to the end of the image where needed
+    Strict,
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use Format::*;
+
+        let name = match self {
+            Bmp => "Bmp",
+            Gif => "Gif",
+            Jpeg => "Jpeg",
+            Png => "Png",
+            Webp => "Webp",
+            Hdr => "Hdr",
+            Qoi => "Qoi",
+            Custom(name) => name,
+        };
+        f.write_str(name)
+    }
+}
+
 impl ImageMeta {
     pub fn is_animation(&self) -> bool {
         self.animation_frames.is_some()
     }
+
+    /// Returns true if the image uses PQ or HLG transfer function
+    pub fn is_hdr(&self) -> bool {
+        self.color_space.is_some_and(|it| it.is_hdr())
+    }
+}
+
+impl ColorSpace {
+    pub fn is_hdr(&self) -> bool {
+        matches!(self.transfer, Some(Transfer::Pq | Transfer::Hlg))
+    }
+}
+
+impl Transfer {
+    /// Transfer characteristics of ITU-T H.273
+    pub fn from_cicp(code: u8) -> Self {
+        use Transfer::*;
+
+        match code {
+            1 | 6 | 14 | 15 => Bt709,
+            8 => Linear,
+            13 => Srgb,
+            16 => Pq,
+            18 => Hlg,
+            code => Unknown(code),
+        }
+    }
+}
+
+impl Limits {
+    /// No limits, as the loaders behaved before `Limits`
+    pub fn unlimited() -> Self {
+        Limits {
+            max_alloc: usize::MAX,
+            max_blocks: usize::MAX,
+            max_bytes_read: u64::MAX,
+            max_dimensions: Dimensions {
+                height: u32::MAX,
+                width: u32::MAX,
+            },
+            max_line_length: usize::MAX,
+        }
+    }
+}
+
+impl Default for Limits {
+    /// Limits enough for ordinary images: 64 MiB of allocation, 2^20 blocks and 64 KiB lines
+    fn default() -> Self {
+        Limits {
+            max_alloc: 64 << 20,
+            max_blocks: 1 << 20,
+            max_line_length: 64 << 10,
+            ..Self::unlimited()
+        }
+    }
+}
+
+impl From<Limits> for LoadOptions {
+    fn from(limits: Limits) -> Self {
+        LoadOptions {
+            limits,
+            ..Default::default()
+        }
+    }
+}
+
+impl From<Registry> for LoadOptions {
+    fn from(registry: Registry) -> Self {
+        LoadOptions {
+            registry: Some(Arc::new(registry)),
+            ..Default::default()
+        }
+    }
+}
+
+impl From<Strictness> for LoadOptions {
+    fn from(strictness: Strictness) -> Self {
+        LoadOptions {
+            strictness,
+            ..Default::default()
+        }
+    }
+}
+
+impl PartialEq for LoadOptions {
+    fn eq(&self, other: &Self) -> bool {
+        let registry = match (&self.registry, &other.registry) {
+            (Some(a), Some(b)) => Arc::ptr_eq(a, b),
+            (a, b) => a.is_none() && b.is_none(),
+        };
+        self.limits == other.limits && registry && self.strictness == other.strictness
+    }
+}
+
+impl Eq for LoadOptions {}
+
+impl Thumbnail {
+    pub fn len(&self) -> u64 {
+        self.range.end - self.range.start
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.range.is_empty()
+    }
+
+    /// Reads the thumbnail data from the image it was found in
+    ///
+    /// The buffer grows as the data is read, so a range beyond the end of the image fails without
+    /// allocating its whole length.
+    pub fn read<R: ?Sized + Read + Seek>(&self, image: &mut R) -> ImageResult<Vec<u8>> {
+        image.seek(SeekFrom::Start(self.range.start))?;
+        let mut result = vec![];
+        let mut buffer = [0u8; 8192];
+        let mut rest = self.len();
+        while 0 < rest {
+            let n = usize::try_from(rest).map_or(buffer.len(), |it| it.min(buffer.len()));
+            image.read_exact(&mut buffer[..n])?;
+            result.extend_from_slice(&buffer[..n]);
+            rest -= n as u64;
+        }
+        Ok(result)
+    }
+}
+
+impl From<u32> for MultiPictureKind {
+    fn from(code: u32) -> Self {
+        use MultiPictureKind::*;
+
+        match code {
+            0x00_0000 => Undefined,
+            0x01_0001 => LargeThumbnailVga,
+            0x01_0002 => LargeThumbnailFullHd,
+            0x02_0001 => Panorama,
+            0x02_0002 => Disparity,
+            0x02_0003 => MultiAngle,
+            0x03_0000 => Baseline,
+            code => Unknown(code),
+        }
+    }
 }
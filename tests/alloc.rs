@@ -0,0 +1,124 @@
+#![cfg(feature = "std")]
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+use image_meta::*;
+
+mod common;
+
+use common::{jpeg_segment, png_with_chunks, FIXTURES};
+
+// Counts the allocations of the current thread
+struct CountingAllocator;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.with(|it| it.set(it.get() + 1));
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+fn count_allocations<T, F: FnOnce() -> T>(f: F) -> (T, usize) {
+    let before = ALLOCATIONS.with(|it| it.get());
+    let result = f();
+    (result, ALLOCATIONS.with(|it| it.get()) - before)
+}
+
+#[test]
+fn test_load_from_buf_without_allocation() {
+    for suffix in FIXTURES {
+        let file = std::fs::read(format!("test-files/paw{}", suffix)).unwrap();
+        let (meta, allocations) = count_allocations(|| load_from_buf(&file));
+        assert!(meta.is_ok());
+        assert_eq!(allocations, 0, "{}", suffix);
+    }
+}
+
+#[test]
+fn test_slice_without_allocation() {
+    let file = png_with_chunks(&[
+        (b"iCCP", b"Profile\0\0\x78\x9c"),
+        (b"tEXt", b"Author\0Someone"),
+        (b"iTXt", b"Title\0\x00\x00en\0Titel\0Paw"),
+        (b"IDAT", b""),
+    ]);
+
+    let ((chunks, texts, profile), allocations) = count_allocations(|| {
+        let chunks = slice::png_chunks(&file)
+            .unwrap()
+            .filter(|it| it.is_ok())
+            .count();
+        let mut texts = [None; 2];
+        for (text, it) in slice::png_texts(&file).unwrap().zip(texts.iter_mut()) {
+            *it = Some(text.unwrap());
+        }
+        let profile = slice::png_icc_profile(&file).unwrap();
+        (chunks, texts, profile)
+    });
+    assert_eq!(allocations, 0);
+    assert_eq!(chunks, 6);
+    assert_eq!(
+        texts,
+        [
+            Some(slice::PngText {
+                compressed: false,
+                keyword: b"Author",
+                language: b"",
+                source: CommentSource::PngText,
+                text: b"Someone",
+                translated_keyword: b"",
+            }),
+            Some(slice::PngText {
+                compressed: false,
+                keyword: b"Title",
+                language: b"en",
+                source: CommentSource::PngInternationalText,
+                text: b"Paw",
+                translated_keyword: b"Titel",
+            }),
+        ]
+    );
+    assert_eq!(
+        profile,
+        Some(slice::IccProfile {
+            data: b"\x78\x9c",
+            name: b"Profile",
+        })
+    );
+
+    // ICC profile split into two APP2 segments
+    let paw = std::fs::read("test-files/paw.jpg").unwrap();
+    let mut file = paw[..2].to_vec();
+    for piece in [&b"ICC_PROFILE\0\x01\x02abc"[..], b"ICC_PROFILE\0\x02\x02de"] {
+        file.extend(jpeg_segment(0xe2, piece));
+    }
+    file.extend_from_slice(&paw[2..]);
+
+    let ((pieces, segments), allocations) = count_allocations(|| {
+        let mut pieces = [&b""[..]; 2];
+        for (piece, it) in slice::jpeg_icc_profile(&file)
+            .unwrap()
+            .zip(pieces.iter_mut())
+        {
+            *it = piece.unwrap();
+        }
+        let segments = slice::jpeg_segments(&file).unwrap().count();
+        (pieces, segments)
+    });
+    assert_eq!(allocations, 0);
+    assert_eq!(pieces, [&b"abc"[..], b"de"]);
+    assert_eq!(segments, slice::jpeg_segments(&paw).unwrap().count() + 2);
+    assert!(slice::jpeg_segments(&paw).unwrap().all(|it| it.is_ok()));
+}
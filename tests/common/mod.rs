@@ -0,0 +1,98 @@
+// Fixtures shared by the integration tests
+#![allow(dead_code)]
+
+// Suffixes of `test-files/paw*` in every format
+pub const FIXTURES: [&str; 11] = [
+    ".bmp",
+    ".gif",
+    ".hdr",
+    ".jpg",
+    ".png",
+    ".qoi",
+    ".webp",
+    ".lossless.webp",
+    "-animation.gif",
+    "-animation.png",
+    "-animation.webp",
+];
+
+pub fn jpeg_segment(marker: u8, data: &[u8]) -> Vec<u8> {
+    let mut result = vec![0xff, marker];
+    result.extend_from_slice(&(data.len() as u16 + 2).to_be_bytes());
+    result.extend_from_slice(data);
+    result
+}
+
+pub fn png_chunk(name: &[u8; 4], data: &[u8]) -> Vec<u8> {
+    let mut result = (data.len() as u32).to_be_bytes().to_vec();
+    result.extend_from_slice(name);
+    result.extend_from_slice(data);
+    result.extend_from_slice(&crc32(&result[4..]).to_be_bytes());
+    result
+}
+
+pub fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0u32, |crc, it| {
+        (0..8).fold(crc ^ u32::from(*it), |crc, _| {
+            (crc >> 1) ^ (0xedb8_8320 & (crc & 1).wrapping_neg())
+        })
+    })
+}
+
+pub fn png_with_chunks(chunks: &[(&[u8; 4], &[u8])]) -> Vec<u8> {
+    let mut result = b"\x89PNG\r\n\x1a\n".to_vec();
+    result.extend(png_chunk(
+        b"IHDR",
+        b"\x00\x00\x01\xfb\x00\x00\x01\x72\x10\x02\x00\x00\x00",
+    ));
+    for (name, data) in chunks {
+        result.extend(png_chunk(name, data));
+    }
+    result.extend(png_chunk(b"IEND", b""));
+    result
+}
+
+// PNG with 64k small chunks before IDAT
+pub fn png_with_many_chunks() -> Vec<u8> {
+    let text = png_chunk(b"tEXt", b"Comment\0Text of 32 bytes for each chunk.");
+    let mut result = png_with_chunks(&[]);
+    let iend = result.split_off(result.len() - 12);
+    for _ in 0..1 << 16 {
+        result.extend_from_slice(&text);
+    }
+    result.extend_from_slice(&png_chunk(b"IDAT", &[0; 16]));
+    result.extend_from_slice(&iend);
+    result
+}
+
+pub fn bmp_with_header(header: &[u8], rest: &[u8]) -> Vec<u8> {
+    let mut result = b"BM".to_vec();
+    result.extend_from_slice(&[0u8; 12]);
+    result.extend_from_slice(&(header.len() as u32 + 4).to_le_bytes());
+    result.extend_from_slice(header);
+    result.extend_from_slice(rest);
+    result
+}
+
+pub fn bmp_info_header(height: i32, bits_per_pixel: u16, compression: u32) -> Vec<u8> {
+    let mut result = 507u32.to_le_bytes().to_vec();
+    result.extend_from_slice(&height.to_le_bytes());
+    result.extend_from_slice(&1u16.to_le_bytes());
+    result.extend_from_slice(&bits_per_pixel.to_le_bytes());
+    result.extend_from_slice(&compression.to_le_bytes());
+    result.extend_from_slice(&[0u8; 12]);
+    result.extend_from_slice(&16u32.to_le_bytes());
+    result.extend_from_slice(&[0u8; 4]);
+    result
+}
+
+pub fn bmp_core_header(signature: &[u8; 2], width: u16, height: u16, data_offset: u32) -> Vec<u8> {
+    let mut result = signature.to_vec();
+    result.extend_from_slice(&[0u8; 8]);
+    result.extend_from_slice(&data_offset.to_le_bytes());
+    result.extend_from_slice(&12u32.to_le_bytes());
+    result.extend_from_slice(&width.to_le_bytes());
+    result.extend_from_slice(&height.to_le_bytes());
+    result.extend_from_slice(&[1, 0, 24, 0]);
+    result
+}
@@ -1,9 +1,15 @@
+#![cfg(feature = "std")]
+
 use std::fs::File;
 use std::io::{BufReader, Cursor};
 
 use image_meta::ColorMode::*;
 use image_meta::*;
 
+mod common;
+
+use common::*;
+
 const DIMS: Dimensions = Dimensions {
     width: 507,
     height: 370,
@@ -29,6 +35,10 @@ fn test_each_loader() {
                 alpha_channel: false,
                 resolution: 8
             },
+            color_space: Some(ColorSpace {
+                transfer: Some(Transfer::Srgb),
+                ..ColorSpace::default()
+            }),
             dimensions: DIMS,
             format: Format::Bmp,
         }
@@ -42,6 +52,7 @@ fn test_each_loader() {
                 alpha_channel: false,
                 resolution: 8
             },
+            color_space: None,
             dimensions: DIMS,
             format: Format::Gif,
         }
@@ -55,6 +66,7 @@ fn test_each_loader() {
                 alpha_channel: false,
                 resolution: 8
             },
+            color_space: None,
             dimensions: DIMS,
             format: Format::Jpeg,
         }
@@ -68,6 +80,7 @@ fn test_each_loader() {
                 alpha_channel: false,
                 resolution: 8
             },
+            color_space: None,
             dimensions: DIMS,
             format: Format::Png,
         }
@@ -81,6 +94,10 @@ fn test_each_loader() {
                 alpha_channel: false,
                 resolution: 8
             },
+            color_space: Some(ColorSpace {
+                transfer: Some(Transfer::Srgb),
+                ..ColorSpace::default()
+            }),
             dimensions: DIMS,
             format: Format::Qoi,
         }
@@ -94,6 +111,7 @@ fn test_each_loader() {
                 alpha_channel: true,
                 resolution: 8
             },
+            color_space: None,
             dimensions: DIMS,
             format: Format::Webp,
         }
@@ -107,6 +125,7 @@ fn test_each_loader() {
                 alpha_channel: false,
                 resolution: 32
             },
+            color_space: None,
             dimensions: DIMS,
             format: Format::Hdr,
         }
@@ -120,6 +139,7 @@ fn test_each_loader() {
                 alpha_channel: true,
                 resolution: 8
             },
+            color_space: None,
             dimensions: DIMS,
             format: Format::Webp,
         }
@@ -137,6 +157,7 @@ fn test_each_loader_for_animation() {
                 alpha_channel: false,
                 resolution: 8
             },
+            color_space: None,
             dimensions: DIMS,
             format: Format::Gif,
         }
@@ -150,6 +171,7 @@ fn test_each_loader_for_animation() {
                 alpha_channel: true,
                 resolution: 8
             },
+            color_space: None,
             dimensions: DIMS,
             format: Format::Png,
         }
@@ -163,6 +185,7 @@ fn test_each_loader_for_animation() {
                 alpha_channel: true,
                 resolution: 8
             },
+            color_space: None,
             dimensions: DIMS,
             format: Format::Webp,
         }
@@ -180,6 +203,10 @@ fn test_guess_loader() {
                 alpha_channel: false,
                 resolution: 8
             },
+            color_space: Some(ColorSpace {
+                transfer: Some(Transfer::Srgb),
+                ..ColorSpace::default()
+            }),
             dimensions: DIMS,
             format: Format::Bmp,
         }
@@ -193,6 +220,7 @@ fn test_guess_loader() {
                 alpha_channel: false,
                 resolution: 8
             },
+            color_space: None,
             dimensions: DIMS,
             format: Format::Gif,
         }
@@ -206,6 +234,7 @@ fn test_guess_loader() {
                 alpha_channel: false,
                 resolution: 8
             },
+            color_space: None,
             dimensions: DIMS,
             format: Format::Jpeg,
         }
@@ -219,6 +248,7 @@ fn test_guess_loader() {
                 alpha_channel: false,
                 resolution: 8
             },
+            color_space: None,
             dimensions: DIMS,
             format: Format::Png,
         }
@@ -232,6 +262,10 @@ fn test_guess_loader() {
                 alpha_channel: false,
                 resolution: 8
             },
+            color_space: Some(ColorSpace {
+                transfer: Some(Transfer::Srgb),
+                ..ColorSpace::default()
+            }),
             dimensions: DIMS,
             format: Format::Qoi,
         }
@@ -245,6 +279,7 @@ fn test_guess_loader() {
                 alpha_channel: true,
                 resolution: 8
             },
+            color_space: None,
             dimensions: DIMS,
             format: Format::Webp,
         }
@@ -262,6 +297,7 @@ fn test_guess_loader_for_animation() {
                 alpha_channel: false,
                 resolution: 8
             },
+            color_space: None,
             dimensions: DIMS,
             format: Format::Gif,
         }
@@ -275,6 +311,7 @@ fn test_guess_loader_for_animation() {
                 alpha_channel: true,
                 resolution: 8
             },
+            color_space: None,
             dimensions: DIMS,
             format: Format::Png,
         }
@@ -288,12 +325,63 @@ fn test_guess_loader_for_animation() {
                 alpha_channel: true,
                 resolution: 8
             },
+            color_space: None,
             dimensions: DIMS,
             format: Format::Webp,
         }
     );
 }
 
+#[test]
+fn test_detect_format() {
+    for (suffix, format) in [
+        (".bmp", Format::Bmp),
+        (".gif", Format::Gif),
+        (".hdr", Format::Hdr),
+        (".jpg", Format::Jpeg),
+        (".png", Format::Png),
+        (".qoi", Format::Qoi),
+        (".webp", Format::Webp),
+        (".lossless.webp", Format::Webp),
+    ] {
+        let file = File::open(format!("test-files/paw{}", suffix)).unwrap();
+        let mut file = BufReader::new(file);
+        assert_eq!(detect_format_from_reader(&mut file).unwrap(), Some(format));
+        // Peeked bytes are not consumed
+        assert_eq!(load(&mut file).unwrap().format, format);
+
+        // Fewer bytes are buffered than the signature
+        let file = File::open(format!("test-files/paw{}", suffix)).unwrap();
+        let mut file = BufReader::with_capacity(4, file);
+        assert_eq!(detect_format_from_reader(&mut file).unwrap(), Some(format));
+        assert_eq!(load(&mut file).unwrap().format, format);
+    }
+
+    let file = std::fs::read("test-files/paw.webp").unwrap();
+    assert_eq!(detect_format(&file[..12]), Some(Format::Webp));
+    assert_eq!(detect_format(&file[..11]), None);
+    assert_eq!(detect_format(b"RIFF\0\0\0\0WAVE"), None);
+
+    // The 2 bytes signatures of BMP need the bitmap header
+    let file = std::fs::read("test-files/paw.bmp").unwrap();
+    assert_eq!(detect_format(&file[..18]), Some(Format::Bmp));
+    assert_eq!(detect_format(&file[..17]), None);
+    for text in [
+        "BMP files start with BM",
+        "BAD files are not images",
+        "CIAO is not an icon",
+        "CP/M is an operating system",
+        "ICON files are not BMP",
+        "PT is not a pointer",
+    ] {
+        assert_eq!(detect_format(text.as_bytes()), None, "{}", text);
+    }
+    assert_eq!(detect_format(b""), None);
+
+    let file = std::fs::read("test-files/bad.dat").unwrap();
+    assert_eq!(detect_format(&file), None);
+}
+
 #[test]
 #[should_panic(expected = "Unsupported")]
 fn test_load_bad() {
@@ -317,3 +405,1602 @@ fn test_load_webp_corrupt_riff_chunk() {
     let mut file = Cursor::new(b"RIFF\x08\x00\x00\x00WEBPVP8 \x00\x00\x00\x00");
     assert!(webp::load(&mut file).is_err());
 }
+
+#[test]
+fn test_load_jpeg_thumbnails() {
+    let paw = std::fs::read("test-files/paw.jpg").unwrap();
+
+    // JFIF with 2x1 RGB thumbnail
+    let mut jfif = b"JFIF\0\x01\x02\x00\x00\x01\x00\x01\x02\x01".to_vec();
+    jfif.extend_from_slice(&[0u8; 6]);
+
+    // EXIF: Empty IFD0 and IFD1 pointing the thumbnail
+    let mut exif = b"Exif\0\0II\x2a\x00\x08\x00\x00\x00".to_vec();
+    exif.extend_from_slice(&[0x00, 0x00, 0x0e, 0x00, 0x00, 0x00]);
+    exif.extend_from_slice(&[0x02, 0x00]);
+    exif.extend_from_slice(&[0x01, 0x02, 0x04, 0x00, 0x01, 0x00, 0x00, 0x00]);
+    exif.extend_from_slice(&44u32.to_le_bytes());
+    exif.extend_from_slice(&[0x02, 0x02, 0x04, 0x00, 0x01, 0x00, 0x00, 0x00]);
+    exif.extend_from_slice(&(paw.len() as u32).to_le_bytes());
+    exif.extend_from_slice(&[0x00, 0x00, 0x00, 0x00]);
+    exif.extend_from_slice(&paw);
+
+    // Photoshop thumbnail resource
+    let mut photoshop = b"Photoshop 3.0\08BIM\x04\x0c\x00\x00".to_vec();
+    photoshop.extend_from_slice(&(28 + paw.len() as u32).to_be_bytes());
+    photoshop.extend_from_slice(&1u32.to_be_bytes());
+    photoshop.extend_from_slice(&507u32.to_be_bytes());
+    photoshop.extend_from_slice(&370u32.to_be_bytes());
+    photoshop.extend_from_slice(&[0u8; 16]);
+    photoshop.extend_from_slice(&paw);
+
+    let mut file = vec![0xff, 0xd8];
+    file.extend(jpeg_segment(0xe0, &jfif));
+    file.extend(jpeg_segment(0xe1, &exif));
+    file.extend(jpeg_segment(0xed, &photoshop));
+    file.extend_from_slice(&paw[2..]);
+
+    let mut file = Cursor::new(file);
+    let thumbnails = jpeg::load_thumbnails(&mut file).unwrap();
+    assert_eq!(
+        thumbnails,
+        vec![
+            Thumbnail {
+                dimensions: Some(Dimensions {
+                    width: 2,
+                    height: 1
+                }),
+                encoding: ThumbnailEncoding::Rgb,
+                range: 20..26,
+                source: ThumbnailSource::Jfif,
+            },
+            Thumbnail {
+                dimensions: Some(DIMS),
+                encoding: ThumbnailEncoding::Jpeg,
+                range: 80..80 + paw.len() as u64,
+                source: ThumbnailSource::Exif,
+            },
+            Thumbnail {
+                dimensions: Some(DIMS),
+                encoding: ThumbnailEncoding::Jpeg,
+                range: 138 + paw.len() as u64..138 + paw.len() as u64 * 2,
+                source: ThumbnailSource::Photoshop,
+            },
+        ]
+    );
+    assert_eq!(thumbnails[1].read(&mut file).unwrap(), paw);
+    assert_eq!(thumbnails[2].read(&mut file).unwrap(), paw);
+
+    // Range beyond the end
+    let thumbnail = Thumbnail {
+        range: 80..80 + (4 << 30),
+        ..thumbnails[1].clone()
+    };
+    assert!(thumbnail.read(&mut file).is_err());
+
+    // Main image is still loadable
+    file.set_position(0);
+    assert_eq!(load(&mut file).unwrap().dimensions, DIMS);
+}
+
+#[test]
+fn test_load_jpeg_thumbnails_none() {
+    let file = File::open("test-files/paw.jpg").unwrap();
+    let mut file = BufReader::new(file);
+    assert_eq!(jpeg::load_thumbnails(&mut file).unwrap(), vec![]);
+}
+
+#[test]
+fn test_load_jpeg_multi_picture() {
+    let paw = std::fs::read("test-files/paw.jpg").unwrap();
+
+    let mut xmp = b"http://ns.adobe.com/xap/1.0/\0".to_vec();
+    xmp.extend_from_slice(b"<x:xmpmeta xmlns:hdrgm=\"http://ns.adobe.com/hdr-gain-map/1.0/\"/>");
+
+    let mut gain_map = vec![0xff, 0xd8];
+    gain_map.extend(jpeg_segment(0xe1, &xmp));
+    gain_map.extend_from_slice(&paw[2..]);
+
+    let mpf_length = 4 + 8 + 2 + 12 + 4 + 32;
+    let primary_length = 2 + (4 + xmp.len()) + (4 + mpf_length) + paw.len() - 2;
+    let mut mpf = b"MPF\0MM\x00\x2a\x00\x00\x00\x08\x00\x01".to_vec();
+    mpf.extend_from_slice(&[0xb0, 0x02, 0x00, 0x07, 0x00, 0x00, 0x00, 0x20]);
+    mpf.extend_from_slice(&26u32.to_be_bytes());
+    mpf.extend_from_slice(&[0x00, 0x00, 0x00, 0x00]);
+    mpf.extend_from_slice(&0x2003_0000u32.to_be_bytes());
+    mpf.extend_from_slice(&(primary_length as u32).to_be_bytes());
+    mpf.extend_from_slice(&[0u8; 8]);
+    mpf.extend_from_slice(&0u32.to_be_bytes());
+    mpf.extend_from_slice(&(gain_map.len() as u32).to_be_bytes());
+    // Relative to the endian field
+    let base = 2 + (4 + xmp.len()) + 4 + 4;
+    mpf.extend_from_slice(&((primary_length - base) as u32).to_be_bytes());
+    mpf.extend_from_slice(&[0u8; 4]);
+    assert_eq!(mpf.len(), mpf_length);
+
+    let mut file = vec![0xff, 0xd8];
+    file.extend(jpeg_segment(0xe1, &xmp));
+    file.extend(jpeg_segment(0xe2, &mpf));
+    file.extend_from_slice(&paw[2..]);
+    assert_eq!(file.len(), primary_length);
+    file.extend_from_slice(&gain_map);
+
+    let mut file = Cursor::new(file);
+    assert_eq!(
+        jpeg::load_multi_picture(&mut file).unwrap(),
+        MultiPicture {
+            gain_map: true,
+            images: vec![
+                MultiPictureImage {
+                    gain_map: false,
+                    kind: MultiPictureKind::Baseline,
+                    range: 0..primary_length as u64,
+                    representative: true,
+                },
+                MultiPictureImage {
+                    gain_map: true,
+                    kind: MultiPictureKind::Undefined,
+                    range: primary_length as u64..(primary_length + gain_map.len()) as u64,
+                    representative: false,
+                },
+            ],
+        }
+    );
+
+    let file = File::open("test-files/paw.jpg").unwrap();
+    let mut file = BufReader::new(file);
+    assert_eq!(
+        jpeg::load_multi_picture(&mut file).unwrap(),
+        MultiPicture::default()
+    );
+}
+
+#[test]
+fn test_load_png_color_space() {
+    let mut mastering_display = vec![];
+    for value in [35400u16, 14600, 8500, 39850, 6550, 2300, 15635, 16450] {
+        mastering_display.extend_from_slice(&value.to_be_bytes());
+    }
+    mastering_display.extend_from_slice(&10_000_000u32.to_be_bytes());
+    mastering_display.extend_from_slice(&50u32.to_be_bytes());
+    let file = png_with_chunks(&[
+        (b"cICP", b"\x09\x10\x00\x01"),
+        (b"mDCv", &mastering_display),
+        (b"cLLI", b"\x00\x98\x96\x80\x00\x0f\x42\x40"),
+    ]);
+    let meta = load_from_buf(&file).unwrap();
+    assert_eq!(
+        meta.color_space,
+        Some(ColorSpace {
+            cicp: Some(Cicp {
+                color_primaries: 9,
+                full_range: true,
+                matrix_coefficients: 0,
+                transfer_characteristics: 16,
+            }),
+            content_light_level: Some(ContentLightLevel {
+                max_content_light_level: 10_000_000,
+                max_frame_average_light_level: 1_000_000,
+            }),
+            mastering_display: Some(MasteringDisplay {
+                max_luminance: 10_000_000,
+                min_luminance: 50,
+                primaries: [[35400, 14600], [8500, 39850], [6550, 2300]],
+                white_point: [15635, 16450],
+            }),
+            transfer: Some(Transfer::Pq),
+            ..ColorSpace::default()
+        })
+    );
+    assert!(meta.is_hdr());
+    assert_eq!(meta.color.resolution, 16);
+
+    let file = png_with_chunks(&[(b"gAMA", b"\x00\x00\xb1\x8f"), (b"sRGB", b"\x00")]);
+    let meta = load_from_buf(&file).unwrap();
+    assert_eq!(
+        meta.color_space,
+        Some(ColorSpace {
+            gamma: Some(45455),
+            rendering_intent: Some(RenderingIntent::Perceptual),
+            transfer: Some(Transfer::Srgb),
+            ..ColorSpace::default()
+        })
+    );
+    assert!(!meta.is_hdr());
+
+    let file = png_with_chunks(&[(b"gAMA", b"\x00\x01\x86\xa0")]);
+    assert_eq!(
+        load_from_buf(&file).unwrap().color_space.unwrap().transfer,
+        Some(Transfer::Linear)
+    );
+}
+
+#[test]
+fn test_load_png_up_to_idat() {
+    // Strict mode reads all chunks
+    let strict = LoadOptions {
+        strictness: Strictness::Strict,
+        ..LoadOptions::default()
+    };
+    for suffix in [".png", "-animation.png"] {
+        let file = std::fs::read(format!("test-files/paw{}", suffix)).unwrap();
+        let meta = load_from_buf(&file).unwrap();
+        assert_eq!(
+            meta,
+            png::load_with_options(&mut Cursor::new(&file), &strict).unwrap(),
+            "{}",
+            suffix
+        );
+        assert_eq!(meta.dimensions, DIMS);
+        assert_eq!(meta.is_animation(), suffix == "-animation.png");
+    }
+
+    // fcTL after IDAT is counted if acTL precedes IDAT
+    let file = png_with_chunks(&[
+        (b"acTL", &[0, 0, 0, 2, 0, 0, 0, 0]),
+        (b"fcTL", &[0; 26]),
+        (b"IDAT", &[0; 16]),
+        (b"fcTL", &[0; 26]),
+        (b"fdAT", &[0; 16]),
+    ]);
+    assert_eq!(load_from_buf(&file).unwrap().animation_frames, Some(2));
+
+    // The chunks after IDAT are not needed
+    let mut file = png_with_chunks(&[(b"IDAT", &[0; 16])]);
+    file.truncate(file.len() - 20);
+    assert_eq!(load_from_buf(&file).unwrap().dimensions, DIMS);
+}
+
+#[test]
+fn test_load_hdr_header() {
+    let file = File::open("test-files/paw.hdr").unwrap();
+    let mut file = BufReader::new(file);
+    assert_eq!(
+        hdr::load_header(&mut file).unwrap(),
+        hdr::Header {
+            comments: vec![],
+            dimensions: DIMS,
+            exposure: None,
+            gamma: Some(1.0),
+            orientation: hdr::Orientation::NegYPosX,
+            pixel_aspect: None,
+            pixel_format: hdr::PixelFormat::Rgbe,
+            primaries: Some([0.0; 8]),
+            software: None,
+        }
+    );
+
+    let mut file = Cursor::new(
+        b"#?RADIANCE\n# Comment\nFORMAT=32-bit_rle_xyze\nEXPOSURE=2\nEXPOSURE= 0.25\nPIXASPECT=1.5\n\
+          SOFTWARE=lighting tool 1.0\n\n+Y 370 +X 507\n",
+    );
+    let header = hdr::load_header(&mut file).unwrap();
+    assert_eq!(header.comments, vec!["Comment".to_owned()]);
+    assert_eq!(header.dimensions, DIMS);
+    assert_eq!(header.exposure, Some(0.5));
+    assert_eq!(header.orientation, hdr::Orientation::PosYPosX);
+    assert_eq!(header.pixel_aspect, Some(1.5));
+    assert_eq!(header.pixel_format, hdr::PixelFormat::Xyze);
+    assert_eq!(header.software.as_deref(), Some("lighting tool 1.0"));
+
+    // Scanlines along X axis
+    let mut file = Cursor::new(b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-X 507 +Y 370\n");
+    let meta = load(&mut file).unwrap();
+    assert_eq!(meta.dimensions, DIMS);
+    assert_eq!(meta.format, Format::Hdr);
+
+    let mut file = Cursor::new(b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y 370 -Y 507\n");
+    assert!(hdr::load(&mut file).is_err());
+    // Unparsable variables are reported only with the header
+    for variable in [
+        "EXPOSURE=bright",
+        "GAMMA=",
+        "PIXASPECT=x",
+        "PRIMARIES=0 0 0",
+    ] {
+        let file = format!("#?RADIANCE\n{}\n\n-Y 370 +X 507\n", variable);
+        assert_eq!(
+            hdr::load(&mut Cursor::new(&file)).unwrap().dimensions,
+            DIMS,
+            "{}",
+            variable
+        );
+        assert_eq!(
+            hdr::load_header(&mut Cursor::new(&file))
+                .unwrap_err()
+                .corruption_kind(),
+            Some(CorruptionKind::InvalidField),
+            "{}",
+            variable
+        );
+    }
+
+    let mut file = Cursor::new(b"#?RADIANCE\n\xff\xfe\n\n-Y 370 +X 507\n");
+    assert!(matches!(
+        hdr::load(&mut file),
+        Err(ImageError::Unsupported(_))
+    ));
+}
+
+#[test]
+fn test_load_bmp_header() {
+    let file = File::open("test-files/paw.bmp").unwrap();
+    let mut file = BufReader::new(file);
+    assert_eq!(
+        bmp::load_header(&mut file).unwrap(),
+        bmp::Header {
+            bits_per_pixel: 24,
+            color_space_type: Some(bmp::ColorSpaceType::Srgb),
+            colors_used: 0,
+            compression: bmp::Compression::Rgb,
+            data_offset: 138,
+            dimensions: DIMS,
+            file_type: bmp::FileType::Bitmap,
+            masks: None,
+            top_down: false,
+            version: bmp::Version::V5,
+        }
+    );
+
+    // Top-down V5 with alpha channel
+    let mut header = bmp_info_header(-370, 32, 3);
+    for mask in [0x00ff_0000u32, 0x0000_ff00, 0x0000_00ff, 0xff00_0000] {
+        header.extend_from_slice(&mask.to_le_bytes());
+    }
+    header.extend_from_slice(b"BGRs");
+    header.extend_from_slice(&[0u8; 64]);
+    let file = bmp_with_header(&header, &[]);
+    let header = bmp::load_header(&mut Cursor::new(&file)).unwrap();
+    assert_eq!(header.compression, bmp::Compression::Bitfields);
+    assert_eq!(
+        header.masks,
+        Some(bmp::Masks {
+            alpha: 0xff00_0000,
+            blue: 0x0000_00ff,
+            green: 0x0000_ff00,
+            red: 0x00ff_0000,
+        })
+    );
+    assert!(header.top_down);
+    assert_eq!(header.version, bmp::Version::V5);
+    let meta = load_from_buf(&file).unwrap();
+    assert_eq!(meta.dimensions, DIMS);
+    assert!(meta.color.alpha_channel);
+    assert_eq!(
+        meta.color_space.and_then(|it| it.transfer),
+        Some(Transfer::Srgb)
+    );
+
+    // BITFIELDS after BITMAPINFOHEADER
+    let masks: Vec<u8> = [0xf800u32, 0x07e0, 0x001f]
+        .iter()
+        .flat_map(|it| it.to_le_bytes())
+        .collect();
+    let file = bmp_with_header(&bmp_info_header(370, 16, 3), &masks);
+    let header = bmp::load_header(&mut Cursor::new(&file)).unwrap();
+    assert_eq!(header.colors_used, 16);
+    assert_eq!(header.version, bmp::Version::Info);
+    assert_eq!(
+        header.masks,
+        Some(bmp::Masks {
+            alpha: 0,
+            blue: 0x001f,
+            green: 0x07e0,
+            red: 0xf800,
+        })
+    );
+
+    // V2 and V3
+    let mut header = bmp_info_header(370, 16, 3);
+    header.extend_from_slice(&masks);
+    let file = bmp_with_header(&header, &[]);
+    assert_eq!(
+        bmp::load_header(&mut Cursor::new(&file)).unwrap().version,
+        bmp::Version::V2
+    );
+    header.extend_from_slice(&[0u8; 4]);
+    let file = bmp_with_header(&header, &[]);
+    assert_eq!(
+        bmp::load_header(&mut Cursor::new(&file)).unwrap().version,
+        bmp::Version::V3
+    );
+
+    // Short OS/2 2.x header
+    let file = bmp_with_header(&bmp_info_header(370, 8, 0)[0..12], &[]);
+    let header = bmp::load_header(&mut Cursor::new(&file)).unwrap();
+    assert_eq!(header.dimensions, DIMS);
+    assert_eq!(header.version, bmp::Version::Os2);
+    assert_eq!(header.bits_per_pixel, 8);
+}
+
+#[test]
+fn test_load_bmp_embedded_image() {
+    let paw = std::fs::read("test-files/paw.png").unwrap();
+    let mut header = bmp_info_header(370, 0, 5);
+    header.splice(0..4, 0u32.to_le_bytes());
+    let mut file = bmp_with_header(&header, &paw);
+    file.splice(10..14, 54u32.to_le_bytes());
+
+    assert_eq!(
+        load_from_buf(&file).unwrap(),
+        ImageMeta {
+            animation_frames: None,
+            color: Color {
+                mode: Rgb,
+                alpha_channel: false,
+                resolution: 8
+            },
+            color_space: None,
+            dimensions: DIMS,
+            format: Format::Bmp,
+        }
+    );
+
+    // The embedded image counts against the limits of the bitmap
+    let png = png_with_chunks(&[(b"tEXt", b"a\0b")]);
+    let mut file = bmp_with_header(&header, &png);
+    file.splice(10..14, 54u32.to_le_bytes());
+    let limits = Limits {
+        max_blocks: 2,
+        ..Limits::default()
+    };
+    assert!(png::load_with_options(&mut Cursor::new(&png), &limits.clone().into()).is_ok());
+    assert_eq!(
+        bmp::load_array_with_options(&mut Cursor::new(&file), &limits.into())
+            .unwrap_err()
+            .corruption_kind(),
+        Some(CorruptionKind::LimitExceeded(Limit::Blocks))
+    );
+}
+
+#[test]
+fn test_load_bmp_os2() {
+    // Bitmap array of two bitmaps
+    let mut file = b"BA\0\0\0\0".to_vec();
+    file.extend_from_slice(&40u32.to_le_bytes());
+    file.extend_from_slice(&[0u8; 4]);
+    file.extend(bmp_core_header(b"BM", 32, 32, 0));
+    file.extend_from_slice(b"BA\0\0\0\0\0\0\0\0\0\0\0\0");
+    file.extend(bmp_core_header(b"BM", 507, 370, 0));
+    assert_eq!(load_from_buf(&file).unwrap().dimensions.width, 32);
+    let metas = bmp::load_array(&mut Cursor::new(&file)).unwrap();
+    assert_eq!(metas.len(), 2);
+    assert_eq!(metas[1].dimensions, DIMS);
+
+    // Icon has AND and XOR masks
+    let file = bmp_core_header(b"IC", 507, 740, 0);
+    assert_eq!(load_from_buf(&file).unwrap().dimensions, DIMS);
+
+    // Color pointer has the mask bitmap and the color bitmap
+    let mut file = bmp_core_header(b"CP", 32, 64, 0);
+    file.extend_from_slice(&[0u8; 6]);
+    file.extend(bmp_core_header(b"CP", 507, 370, 0));
+    let header = bmp::load_header(&mut Cursor::new(&file)).unwrap();
+    assert_eq!(header.dimensions, DIMS);
+    assert_eq!(header.file_type, bmp::FileType::ColorPointer);
+
+    // Not BMP
+    assert!(matches!(
+        load_from_buf(b"ICON\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0"),
+        Err(ImageError::Unsupported(_))
+    ));
+}
+
+#[test]
+fn test_load_qoi_verify() {
+    let file = File::open("test-files/paw.qoi").unwrap();
+    let mut file = BufReader::new(file);
+    assert_eq!(qoi::verify(&mut file).unwrap().dimensions, DIMS);
+
+    let header = b"qoif\x00\x00\x01\xfb\x00\x00\x01\x72\x04\x01";
+    let mut file = header.to_vec();
+    file.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 1]);
+    let meta = qoi::verify(&mut Cursor::new(&file)).unwrap();
+    assert!(meta.color.alpha_channel);
+    assert_eq!(meta.color_space.unwrap().transfer, Some(Transfer::Linear));
+
+    // No end marker
+    let file = header.to_vec();
+    assert!(qoi::load(&mut Cursor::new(&file)).is_ok());
+    assert!(qoi::verify(&mut Cursor::new(&file)).is_err());
+
+    // Invalid colorspace
+    let mut file = b"qoif\x00\x00\x01\xfb\x00\x00\x01\x72\x04\x02".to_vec();
+    file.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 1]);
+    assert_eq!(
+        qoi::load(&mut Cursor::new(&file)).unwrap().color_space,
+        None
+    );
+    assert!(qoi::verify(&mut Cursor::new(&file)).is_err());
+
+    // Empty
+    let mut file = b"qoif\x00\x00\x00\x00\x00\x00\x01\x72\x04\x00".to_vec();
+    file.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 1]);
+    assert!(qoi::verify(&mut Cursor::new(&file)).is_err());
+
+    // Too large
+    let mut file = b"qoif\x00\x01\x00\x00\x00\x01\x00\x00\x04\x00".to_vec();
+    file.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 1]);
+    assert!(qoi::verify(&mut Cursor::new(&file)).is_err());
+}
+
+#[test]
+fn test_load_gif_header() {
+    let file = File::open("test-files/paw.gif").unwrap();
+    let mut file = BufReader::new(file);
+    let header = gif::load_header(&mut file).unwrap();
+    assert_eq!(
+        header,
+        gif::Header {
+            background_color_index: 0,
+            color_resolution: 8,
+            dimensions: DIMS,
+            global_color_table: Some(256),
+            global_color_table_sorted: false,
+            pixel_aspect_ratio: 0,
+            version: gif::Version::Gif89a,
+        }
+    );
+    assert_eq!(header.aspect_ratio(), None);
+
+    let file = File::open("test-files/paw.gif").unwrap();
+    let mut file = BufReader::new(file);
+    let palette = gif::load_palette(&mut file).unwrap().unwrap();
+    assert_eq!(palette.len(), 256);
+    assert!(palette.iter().all(|it| it.alpha == 255));
+
+    // 4 colors, sorted, 2 bits resolution
+    let file =
+        b"GIF87a\x02\x00\x01\x00\x99\x03\x31\x00\x00\x00\xff\x00\x00\x00\xff\x00\x00\x00\xff\x3b";
+    let header = gif::load_header(&mut Cursor::new(file)).unwrap();
+    assert_eq!(header.background_color_index, 3);
+    assert_eq!(header.color_resolution, 2);
+    assert_eq!(header.global_color_table, Some(4));
+    assert!(header.global_color_table_sorted);
+    assert_eq!(header.aspect_ratio(), Some(1.0));
+    assert_eq!(header.version, gif::Version::Gif87a);
+    assert_eq!(
+        gif::load_palette(&mut Cursor::new(file)).unwrap().unwrap()[1],
+        PaletteEntry {
+            alpha: 255,
+            blue: 0,
+            green: 0,
+            red: 255
+        }
+    );
+    assert_eq!(
+        load_from_buf(file).unwrap().dimensions,
+        Dimensions {
+            width: 2,
+            height: 1
+        }
+    );
+
+    // No global color table
+    let file = b"GIF89a\x01\x00\x01\x00\x00\x00\x00\x3b";
+    assert_eq!(gif::load_palette(&mut Cursor::new(file)).unwrap(), None);
+}
+
+#[test]
+fn test_load_palettes() {
+    fn entry(red: u8, green: u8, blue: u8, alpha: u8) -> PaletteEntry {
+        PaletteEntry {
+            alpha,
+            blue,
+            green,
+            red,
+        }
+    }
+
+    // GIF: Global table, and transparent color in local and global tables
+    let mut file = b"GIF89a\x02\x00\x01\x00\x80\x00\x00\xff\x00\x00\x00\xff\x00".to_vec();
+    file.extend_from_slice(b"\x21\xf9\x04\x01\x00\x00\x01\x00");
+    file.extend_from_slice(b"\x2c\x00\x00\x00\x00\x02\x00\x01\x00\x80");
+    file.extend_from_slice(b"\x00\x00\x01\x01\x01\x01\x02\x02\x44\x01\x00");
+    file.extend_from_slice(b"\x2c\x00\x00\x00\x00\x02\x00\x01\x00\x00\x02\x02\x44\x01\x00");
+    file.extend_from_slice(b"\x21\xf9\x04\x01\x00\x00\x00\x00");
+    file.extend_from_slice(b"\x2c\x00\x00\x00\x00\x02\x00\x01\x00\x00\x02\x02\x44\x01\x00\x3b");
+    assert_eq!(
+        load_palettes(&mut Cursor::new(&file)).unwrap(),
+        vec![
+            Palette {
+                entries: vec![entry(255, 0, 0, 255), entry(0, 255, 0, 255)],
+                source: PaletteSource::GifGlobal,
+            },
+            Palette {
+                entries: vec![entry(0, 0, 1, 255), entry(1, 1, 1, 0)],
+                source: PaletteSource::GifLocal(0),
+            },
+            Palette {
+                entries: vec![entry(255, 0, 0, 0), entry(0, 255, 0, 255)],
+                source: PaletteSource::GifFrame(2),
+            },
+        ]
+    );
+    assert_eq!(load_from_buf(&file).unwrap().animation_frames, Some(3));
+
+    // PNG: PLTE and shorter tRNS
+    let mut file = b"\x89PNG\r\n\x1a\n".to_vec();
+    file.extend(png_chunk(
+        b"IHDR",
+        b"\x00\x00\x01\xfb\x00\x00\x01\x72\x04\x03\x00\x00\x00",
+    ));
+    file.extend(png_chunk(b"PLTE", b"\x00\x00\x00\xff\xff\xff\x80\x80\x80"));
+    file.extend(png_chunk(b"tRNS", b"\x00\x7f"));
+    file.extend(png_chunk(b"IDAT", b""));
+    file.extend(png_chunk(b"IEND", b""));
+    assert_eq!(
+        load_palettes(&mut Cursor::new(&file)).unwrap(),
+        vec![Palette {
+            entries: vec![
+                entry(0, 0, 0, 0),
+                entry(255, 255, 255, 127),
+                entry(128, 128, 128, 255)
+            ],
+            source: PaletteSource::Png,
+        }]
+    );
+
+    // BMP: 4 bits with 2 colors
+    let mut header = bmp_info_header(370, 4, 0);
+    header.splice(28..32, 2u32.to_le_bytes());
+    let file = bmp_with_header(&header, b"\x00\x00\xff\x00\xff\x00\x00\x00");
+    assert_eq!(
+        load_palettes(&mut Cursor::new(&file)).unwrap(),
+        vec![Palette {
+            entries: vec![entry(255, 0, 0, 255), entry(0, 0, 255, 255)],
+            source: PaletteSource::Bmp,
+        }]
+    );
+    assert_eq!(
+        load_from_buf(&file).unwrap().color,
+        Color {
+            alpha_channel: false,
+            mode: Indexed,
+            resolution: 4,
+        }
+    );
+
+    let file = File::open("test-files/paw.jpg").unwrap();
+    let mut file = BufReader::new(file);
+    assert_eq!(load_palettes(&mut file).unwrap(), vec![]);
+}
+
+#[test]
+fn test_load_comments() {
+    fn comment(keyword: Option<&str>, source: CommentSource, text: &str) -> Comment {
+        Comment {
+            keyword: keyword.map(str::to_owned),
+            source,
+            text: text.to_owned(),
+        }
+    }
+
+    // GIF: Comment split into two sub-blocks
+    let file = b"GIF89a\x01\x00\x01\x00\x00\x00\x00\x21\xfe\x05Hello\x06 world\x00\x3b";
+    assert_eq!(
+        load_comments(&mut Cursor::new(file)).unwrap(),
+        vec![comment(None, CommentSource::Gif, "Hello world")]
+    );
+
+    // HDR
+    let file = b"#?RADIANCE\n#  made by hand \nFORMAT=32-bit_rle_rgbe\n\n-Y 370 +X 507\n";
+    assert_eq!(
+        load_comments(&mut Cursor::new(file)).unwrap(),
+        vec![comment(None, CommentSource::Hdr, "made by hand")]
+    );
+
+    // JPEG: COM before SOF
+    let paw = std::fs::read("test-files/paw.jpg").unwrap();
+    let mut file = paw[..2].to_vec();
+    file.extend(jpeg_segment(0xfe, b"First"));
+    file.extend(jpeg_segment(0xfe, b"Second"));
+    file.extend_from_slice(&paw[2..]);
+    assert_eq!(
+        load_comments(&mut Cursor::new(&file)).unwrap(),
+        vec![
+            comment(None, CommentSource::Jpeg, "First"),
+            comment(None, CommentSource::Jpeg, "Second"),
+        ]
+    );
+
+    // PNG: tEXt in Latin-1, zTXt, and uncompressed/compressed iTXt
+    let compressed = b"\x78\x9c\x4b\xce\xcf\x2d\x28\x4a\x2d\x2e\x4e\x4d\x01\x00\x17\x3f\x04\x36";
+    let mut ztxt = b"Comment\0\0".to_vec();
+    ztxt.extend_from_slice(compressed);
+    let mut itxt = b"Comment\0\x01\x00ja\0\0".to_vec();
+    itxt.extend_from_slice(compressed);
+    let file = png_with_chunks(&[
+        (b"tEXt", b"Author\0Jos\xe9"),
+        (b"zTXt", &ztxt),
+        (b"iTXt", b"Title\0\x00\x00en\0Titel\0\xe2\x9c\x93"),
+        (b"iTXt", &itxt),
+    ]);
+    assert_eq!(
+        load_comments(&mut Cursor::new(&file)).unwrap(),
+        vec![
+            comment(Some("Author"), CommentSource::PngText, "Jos\u{e9}"),
+            comment(
+                Some("Comment"),
+                CommentSource::PngCompressedText,
+                "compressed"
+            ),
+            comment(
+                Some("Title"),
+                CommentSource::PngInternationalText,
+                "\u{2713}"
+            ),
+            comment(
+                Some("Comment"),
+                CommentSource::PngInternationalText,
+                "compressed"
+            ),
+        ]
+    );
+
+    // PNG: Malformed chunks are skipped unless strict
+    let file = png_with_chunks(&[
+        (b"tEXt", b"No separator"),
+        (b"zTXt", b"Comment  not zlib"),
+        (b"iTXt", b"Title  "),
+        (b"tEXt", b"Author Someone"),
+    ]);
+    for strictness in [Strictness::Lenient, Strictness::Normal] {
+        let options = LoadOptions {
+            strictness,
+            ..LoadOptions::default()
+        };
+        assert_eq!(
+            load_comments_with_options(&mut Cursor::new(&file), &options).unwrap(),
+            vec![comment(Some("Author"), CommentSource::PngText, "Someone")]
+        );
+    }
+    let options = LoadOptions {
+        strictness: Strictness::Strict,
+        ..LoadOptions::default()
+    };
+    assert!(load_comments_with_options(&mut Cursor::new(&file), &options).is_err());
+
+    // PNG: Image data is skipped without charging the allocation limit
+    let file = png_with_chunks(&[(b"IDAT", &[0; 1 << 16]), (b"tEXt", b"Author\0Someone")]);
+    let options = LoadOptions::from(Limits {
+        max_alloc: 1 << 10,
+        ..Limits::default()
+    });
+    assert_eq!(
+        load_comments_with_options(&mut Cursor::new(&file), &options).unwrap(),
+        vec![comment(Some("Author"), CommentSource::PngText, "Someone")]
+    );
+
+    let file = File::open("test-files/paw.webp").unwrap();
+    let mut file = BufReader::new(file);
+    assert_eq!(load_comments(&mut file).unwrap(), vec![]);
+}
+
+#[test]
+fn test_load_with_limits() {
+    fn load_limited(file: &[u8], limits: &Limits) -> ImageResult<ImageMeta> {
+        load_with_options(&mut Cursor::new(file), &limits.clone().into())
+    }
+
+    let suffixes = [".bmp", ".gif", ".hdr", ".jpg", ".png", ".qoi", ".webp"];
+    for suffix in suffixes {
+        let file = std::fs::read(format!("test-files/paw{}", suffix)).unwrap();
+        assert_eq!(
+            load_limited(&file, &Limits::unlimited()).unwrap(),
+            load_from_buf(&file).unwrap()
+        );
+
+        let limits = Limits {
+            max_dimensions: Dimensions {
+                width: 507,
+                height: 369,
+            },
+            ..Limits::default()
+        };
+        assert_eq!(
+            load_limited(&file, &limits).unwrap_err().corruption_kind(),
+            Some(CorruptionKind::LimitExceeded(Limit::Dimensions)),
+            "{}",
+            suffix
+        );
+
+        let limits = Limits {
+            max_bytes_read: 8,
+            ..Limits::default()
+        };
+        assert_eq!(
+            load_limited(&file, &limits).unwrap_err().corruption_kind(),
+            Some(CorruptionKind::LimitExceeded(Limit::BytesRead)),
+            "{}",
+            suffix
+        );
+    }
+
+    // Chunks visited
+    let file = png_with_chunks(&[(b"tEXt", b"a\0b"), (b"tEXt", b"c\0d")]);
+    let limits = Limits {
+        max_blocks: 2,
+        ..Limits::default()
+    };
+    assert_eq!(
+        load_limited(&file, &limits).unwrap_err().corruption_kind(),
+        Some(CorruptionKind::LimitExceeded(Limit::Blocks))
+    );
+
+    assert_eq!(
+        png::load_comments_with_options(&mut Cursor::new(&file), &limits.clone().into())
+            .unwrap_err()
+            .corruption_kind(),
+        Some(CorruptionKind::LimitExceeded(Limit::Blocks))
+    );
+    assert_eq!(
+        load_comments_with_options(&mut Cursor::new(&file), &limits.into())
+            .unwrap_err()
+            .corruption_kind(),
+        Some(CorruptionKind::LimitExceeded(Limit::Blocks))
+    );
+
+    // Chunk length claiming 4 GiB is not allocated
+    let mut file = png_with_chunks(&[]);
+    file.truncate(33);
+    file.extend_from_slice(b"\xff\xff\xff\xf0tEXt");
+    assert_eq!(
+        load_comments(&mut Cursor::new(&file))
+            .unwrap_err()
+            .corruption_kind(),
+        Some(CorruptionKind::LimitExceeded(Limit::Alloc))
+    );
+
+    // Header line without terminator
+    let mut file = b"#?RADIANCE\n#".to_vec();
+    file.resize(100, b'x');
+    let limits = Limits {
+        max_line_length: 64,
+        ..Limits::default()
+    };
+    assert_eq!(
+        load_limited(&file, &limits).unwrap_err().corruption_kind(),
+        Some(CorruptionKind::LimitExceeded(Limit::LineLength))
+    );
+}
+
+#[test]
+fn test_error_details() {
+    // Invalid color type in IHDR
+    let mut file = png_with_chunks(&[]);
+    file[25] = 5;
+    let err = load_from_buf(&file).unwrap_err();
+    assert_eq!(
+        err.corruption(),
+        Some(&Corruption {
+            block: Some(Block::Chunk(*b"IHDR")),
+            format: Format::Png,
+            kind: CorruptionKind::InvalidField,
+            message: "Invalid color type: 5".into(),
+            offset: Some(26),
+            partial: None,
+        })
+    );
+    assert_eq!(
+        err.to_string(),
+        "Corrupt image: Invalid color type: 5 (Png, chunk IHDR, offset 26)"
+    );
+
+    // Unknown block after the header
+    let file = b"GIF89a\x01\x00\x01\x00\x00\x00\x00\x99";
+    let err = load_from_buf(file).unwrap_err();
+    let corruption = err.corruption().unwrap();
+    assert_eq!(corruption.block, Some(Block::Label(0x99)));
+    assert_eq!(corruption.kind, CorruptionKind::UnknownBlock);
+    assert_eq!(corruption.offset, Some(14));
+
+    // Unexpected EOF
+    let paw = std::fs::read("test-files/paw.jpg").unwrap();
+    let err = load_from_buf(&paw[..100]).unwrap_err();
+    let corruption = err.corruption().unwrap();
+    assert_eq!(corruption.format, Format::Jpeg);
+    assert_eq!(corruption.kind, CorruptionKind::Truncated);
+    assert!(corruption.offset.is_some());
+
+    match load_from_buf(b"Not an image") {
+        Err(ImageError::Unsupported(unsupported)) => {
+            assert_eq!(unsupported.signature, b"Not an image");
+            assert_eq!(unsupported.tried.len(), 7);
+        }
+        otherwise => panic!("Unexpected result: {:?}", otherwise),
+    }
+}
+
+#[test]
+fn test_load_truncated() {
+    for suffix in ["-animation.gif", "-animation.png", "-animation.webp"] {
+        let file = std::fs::read(format!("test-files/paw{}", suffix)).unwrap();
+        let full = load_from_buf(&file).unwrap();
+
+        let err = load_from_buf(&file[..file.len() / 2]).unwrap_err();
+        assert!(err.is_truncated(), "{}", suffix);
+        let partial = err.partial().unwrap();
+        assert_eq!(partial.dimensions, DIMS);
+        assert_eq!(partial.format, full.format);
+        let frames = partial.animation_frames.unwrap_or_default();
+        assert!(
+            0 < frames && frames < full.animation_frames.unwrap(),
+            "{}: {}",
+            suffix,
+            frames
+        );
+
+        // The same through the stream
+        let mut loader = stream::StreamLoader::new();
+        loader.feed(&file[..file.len() / 2]).unwrap();
+        assert_eq!(loader.finish().unwrap_err().partial(), Some(partial));
+    }
+
+    // Cut off before the first IDAT
+    let file = std::fs::read("test-files/paw.png").unwrap();
+    let err = load_from_buf(&file[..40]).unwrap_err();
+    assert!(err.is_truncated());
+    assert_eq!(err.partial().unwrap().dimensions, DIMS);
+
+    // Cut off in the header
+    let err = load_from_buf(&file[..20]).unwrap_err();
+    assert!(err.is_truncated());
+    assert_eq!(err.partial(), None);
+
+    let file = std::fs::read("test-files/paw.jpg").unwrap();
+    let err = load_from_buf(&file[..100]).unwrap_err();
+    assert!(err.is_truncated());
+    assert_eq!(err.partial(), None);
+}
+
+#[test]
+fn test_load_with_strictness() {
+    fn load_strictly(file: &[u8], strictness: Strictness) -> ImageResult<ImageMeta> {
+        load_with_options(&mut Cursor::new(file), &strictness.into())
+    }
+    fn kind_of(result: ImageResult<ImageMeta>) -> Option<CorruptionKind> {
+        result.unwrap_err().corruption_kind()
+    }
+
+    for suffix in FIXTURES {
+        let file = std::fs::read(format!("test-files/paw{}", suffix)).unwrap();
+        let meta = load_from_buf(&file).unwrap();
+        for strictness in [Strictness::Lenient, Strictness::Strict] {
+            assert_eq!(
+                load_strictly(&file, strictness).unwrap(),
+                meta,
+                "{}",
+                suffix
+            );
+        }
+    }
+
+    // PNG: CRC
+    let file = std::fs::read("test-files/paw.png").unwrap();
+    let mut corrupt = file.clone();
+    corrupt[32] ^= 1;
+    assert!(load_from_buf(&corrupt).is_ok());
+    let err = load_strictly(&corrupt, Strictness::Strict).unwrap_err();
+    let corruption = err.corruption().unwrap();
+    assert_eq!(corruption.kind, CorruptionKind::BadChecksum);
+    assert_eq!(corruption.block, Some(Block::Chunk(*b"IHDR")));
+
+    // PNG: Trailing garbage
+    let mut trailing = file.clone();
+    trailing.extend_from_slice(b"garbage");
+    assert!(load_strictly(&trailing, Strictness::Lenient).is_ok());
+    assert_eq!(
+        kind_of(load_strictly(&trailing, Strictness::Strict)),
+        Some(CorruptionKind::UnexpectedBlock)
+    );
+
+    // PNG: Chunk ordering
+    let file = png_with_chunks(&[(b"IDAT", b""), (b"gAMA", b"\x00\x00\xb1\x8f")]);
+    assert!(load_from_buf(&file).is_ok());
+    let err = load_strictly(&file, Strictness::Strict).unwrap_err();
+    let corruption = err.corruption().unwrap();
+    assert_eq!(corruption.kind, CorruptionKind::UnexpectedBlock);
+    assert_eq!(corruption.block, Some(Block::Chunk(*b"gAMA")));
+
+    // PNG: Missing IEND
+    let file = std::fs::read("test-files/paw-animation.png").unwrap();
+    let cut = &file[..file.len() - 12];
+    assert!(load_from_buf(cut).unwrap_err().is_truncated());
+    assert_eq!(
+        load_strictly(cut, Strictness::Lenient).unwrap(),
+        load_from_buf(&file).unwrap()
+    );
+
+    // GIF: Unknown extension
+    let file = std::fs::read("test-files/paw-animation.gif").unwrap();
+    let mut unknown = file[..file.len() - 1].to_vec();
+    unknown.extend_from_slice(b"\x21\x99\x01\x00\x00\x3b");
+    assert_eq!(
+        load_strictly(&unknown, Strictness::Lenient).unwrap(),
+        load_from_buf(&file).unwrap()
+    );
+    for strictness in [Strictness::Normal, Strictness::Strict] {
+        assert_eq!(
+            kind_of(load_strictly(&unknown, strictness)),
+            Some(CorruptionKind::UnknownBlock)
+        );
+    }
+
+    // GIF: Missing trailer and trailing garbage
+    let cut = &file[..file.len() - 1];
+    assert!(load_from_buf(cut).unwrap_err().is_truncated());
+    assert!(load_strictly(cut, Strictness::Lenient).is_ok());
+    let mut garbage = cut.to_vec();
+    garbage.push(0x00);
+    assert_eq!(
+        kind_of(load_from_buf(&garbage)),
+        Some(CorruptionKind::UnknownBlock)
+    );
+    assert!(load_strictly(&garbage, Strictness::Lenient).is_ok());
+
+    // JPEG: Marker sequence
+    let file = std::fs::read("test-files/paw.jpg").unwrap();
+    let mut sequence = file[..2].to_vec();
+    sequence.extend(jpeg_segment(0xda, b"\x00"));
+    sequence.extend_from_slice(&file[2..]);
+    assert!(load_from_buf(&sequence).is_ok());
+    let err = load_strictly(&sequence, Strictness::Strict).unwrap_err();
+    let corruption = err.corruption().unwrap();
+    assert_eq!(corruption.kind, CorruptionKind::UnexpectedBlock);
+    assert_eq!(corruption.block, Some(Block::Marker(0xda)));
+
+    // RIFF: Size
+    let file = std::fs::read("test-files/paw.webp").unwrap();
+    let mut larger = file.clone();
+    larger[4] = larger[4].wrapping_add(16);
+    assert!(load_from_buf(&larger).unwrap_err().is_truncated());
+    assert!(load_strictly(&larger, Strictness::Lenient).is_ok());
+    let mut trailing = file.clone();
+    trailing.extend_from_slice(b"garbage");
+    assert!(load_from_buf(&trailing).is_ok());
+    assert_eq!(
+        kind_of(load_strictly(&trailing, Strictness::Strict)),
+        Some(CorruptionKind::UnexpectedBlock)
+    );
+}
+
+#[test]
+fn test_check_png_integrity() {
+    fn check(file: &[u8]) -> Vec<(CorruptionKind, Option<Block>)> {
+        png::check_integrity(&mut Cursor::new(file))
+            .unwrap()
+            .into_iter()
+            .map(|it| (it.kind, it.block))
+            .collect()
+    }
+
+    for suffix in [".png", "-animation.png"] {
+        let file = std::fs::read(format!("test-files/paw{}", suffix)).unwrap();
+        assert_eq!(check(&file), vec![], "{}", suffix);
+    }
+
+    // Bit rot in IHDR and the last IDAT is reported without stopping
+    let file = std::fs::read("test-files/paw.png").unwrap();
+    let mut corrupt = file.clone();
+    corrupt[20] ^= 1;
+    let length = corrupt.len();
+    corrupt[length - 20] ^= 1;
+    let findings = png::check_integrity(&mut Cursor::new(&corrupt)).unwrap();
+    assert_eq!(
+        findings.iter().map(|it| it.block).collect::<Vec<_>>(),
+        vec![Some(Block::Chunk(*b"IHDR")), Some(Block::Chunk(*b"IDAT"))]
+    );
+    assert!(findings
+        .iter()
+        .all(|it| it.kind == CorruptionKind::BadChecksum));
+    assert_eq!(findings[0].offset, Some(8));
+
+    // Missing IEND, and trailing data
+    assert_eq!(
+        check(&file[..file.len() - 12]),
+        vec![(CorruptionKind::Truncated, None)]
+    );
+    let mut trailing = file.clone();
+    trailing.extend_from_slice(b"garbage");
+    assert_eq!(
+        check(&trailing),
+        vec![(CorruptionKind::UnexpectedBlock, None)]
+    );
+
+    // Truncated in a chunk
+    assert_eq!(
+        check(&file[..file.len() / 2]),
+        vec![(CorruptionKind::Truncated, Some(Block::Chunk(*b"IDAT")))]
+    );
+
+    // Ordering
+    let file = png_with_chunks(&[
+        (b"IDAT", b""),
+        (b"gAMA", b"\x00\x00\xb1\x8f"),
+        (b"IDAT", b""),
+    ]);
+    assert_eq!(
+        check(&file),
+        vec![
+            (
+                CorruptionKind::UnexpectedBlock,
+                Some(Block::Chunk(*b"gAMA"))
+            ),
+            (
+                CorruptionKind::UnexpectedBlock,
+                Some(Block::Chunk(*b"IDAT"))
+            ),
+        ]
+    );
+    assert_eq!(
+        check(&png_with_chunks(&[])),
+        vec![(
+            CorruptionKind::UnexpectedBlock,
+            Some(Block::Chunk(*b"IEND"))
+        )]
+    );
+
+    assert!(matches!(
+        png::check_integrity(&mut Cursor::new(b"GIF89a\x00\x00")),
+        Err(ImageError::InvalidSignature)
+    ));
+}
+
+#[test]
+fn test_check_jpeg_structure() {
+    fn check(file: &[u8]) -> Vec<(CorruptionKind, Option<Block>)> {
+        jpeg::check_structure(&mut Cursor::new(file))
+            .unwrap()
+            .into_iter()
+            .map(|it| (it.kind, it.block))
+            .collect()
+    }
+
+    let file = std::fs::read("test-files/paw.jpg").unwrap();
+    assert_eq!(check(&file), vec![]);
+
+    // The header is intact, but the scan is not
+    let cut = &file[..file.len() / 2];
+    assert!(load_from_buf(cut).is_ok());
+    assert_eq!(
+        check(cut),
+        vec![(CorruptionKind::Truncated, Some(Block::Marker(0xda)))]
+    );
+
+    // Missing EOI, and garbage after EOI
+    assert_eq!(
+        check(&file[..file.len() - 2]),
+        vec![(CorruptionKind::Truncated, Some(Block::Marker(0xda)))]
+    );
+    let err = jpeg::load(&mut Cursor::new(&file[..2])).unwrap_err();
+    assert!(err.is_truncated());
+    assert_eq!(check(&file[..2]), vec![(CorruptionKind::Truncated, None)]);
+    let mut trailing = file.clone();
+    trailing.extend_from_slice(b"garbage");
+    assert_eq!(
+        check(&trailing),
+        vec![(CorruptionKind::UnexpectedBlock, None)]
+    );
+
+    // Bad segment length and misplaced markers are reported without stopping
+    let mut broken = file[..2].to_vec();
+    broken.extend_from_slice(b"\xff\xd0");
+    broken.extend_from_slice(b"\xff\xfe\x00\x01");
+    broken.extend_from_slice(&file[2..]);
+    let findings = jpeg::check_structure(&mut Cursor::new(&broken)).unwrap();
+    assert_eq!(
+        findings
+            .iter()
+            .map(|it| (it.kind, it.block, it.offset))
+            .collect::<Vec<_>>(),
+        vec![
+            (
+                CorruptionKind::UnexpectedBlock,
+                Some(Block::Marker(0xd0)),
+                Some(2)
+            ),
+            (
+                CorruptionKind::InvalidField,
+                Some(Block::Marker(0xfe)),
+                Some(4)
+            ),
+        ]
+    );
+
+    // Segment beyond the end
+    let mut long = file[..2].to_vec();
+    long.extend(jpeg_segment(0xe1, b"Exif\x00\x00"));
+    long[5] = 0xff;
+    assert_eq!(
+        check(&long),
+        vec![(CorruptionKind::Truncated, Some(Block::Marker(0xe1)))]
+    );
+}
+
+#[test]
+fn test_walk_blocks() {
+    fn truncated_at(err: ImageError) -> (Option<Block>, Option<u64>) {
+        match err {
+            ImageError::CorruptImage(it) if it.kind == CorruptionKind::Truncated => {
+                (it.block, it.offset)
+            }
+            err => panic!("{}", err),
+        }
+    }
+
+    // PNG
+    let file = std::fs::read("test-files/paw-animation.png").unwrap();
+    let chunks = png::chunks(&mut Cursor::new(&file))
+        .unwrap()
+        .collect::<ImageResult<Vec<_>>>()
+        .unwrap();
+    assert_eq!(chunks[0].name, *b"IHDR");
+    assert_eq!(chunks[0].offset, 8);
+    assert_eq!(chunks[0].length, 13);
+    assert_eq!(chunks[0].data, None);
+    assert_eq!(chunks.last().unwrap().name, *b"IEND");
+    assert_eq!(
+        chunks.iter().filter(|it| it.name == *b"fcTL").count(),
+        load_from_buf(&file).unwrap().animation_frames.unwrap()
+    );
+    let with_data = png::chunks(&mut Cursor::new(&file))
+        .unwrap()
+        .with_data()
+        .map(|it| it.unwrap().data.unwrap())
+        .collect::<Vec<_>>();
+    let expected = slice::png_chunks(&file)
+        .unwrap()
+        .map(|it| it.unwrap().data.to_vec())
+        .collect::<Vec<_>>();
+    assert_eq!(with_data, expected);
+
+    // Cut inside the last chunk
+    let file = png_with_chunks(&[(b"tEXt", b"Author\0Someone")]);
+    let file = &file[..file.len() - 12 - 6];
+    for with_data in [false, true] {
+        let mut cursor = Cursor::new(file);
+        let chunks = png::chunks(&mut cursor).unwrap();
+        let chunks = if with_data {
+            chunks.with_data()
+        } else {
+            chunks
+        };
+        let chunks = chunks.collect::<Vec<_>>();
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(
+            truncated_at(chunks.into_iter().last().unwrap().unwrap_err()),
+            (Some(Block::Chunk(*b"tEXt")), Some(33))
+        );
+    }
+
+    let file = std::fs::read("test-files/paw-animation.png").unwrap();
+    let limits = Limits {
+        max_blocks: 2,
+        ..Limits::default()
+    };
+    let chunks = png::chunks(&mut Cursor::new(&file))
+        .unwrap()
+        .with_options(limits.into())
+        .collect::<Vec<_>>();
+    assert_eq!(chunks.len(), 3);
+    assert_eq!(
+        chunks[2].as_ref().unwrap_err().corruption_kind(),
+        Some(CorruptionKind::LimitExceeded(Limit::Blocks))
+    );
+    let limits = Limits {
+        max_alloc: 16,
+        ..Limits::default()
+    };
+    let err = png::chunks(&mut Cursor::new(&file))
+        .unwrap()
+        .with_data()
+        .with_options(limits.into())
+        .find_map(Result::err)
+        .unwrap();
+    assert_eq!(
+        err.corruption_kind(),
+        Some(CorruptionKind::LimitExceeded(Limit::Alloc))
+    );
+
+    // JPEG
+    let file = std::fs::read("test-files/paw.jpg").unwrap();
+    let segments = jpeg::segments(&mut Cursor::new(&file))
+        .unwrap()
+        .with_data()
+        .collect::<ImageResult<Vec<_>>>()
+        .unwrap();
+    assert_eq!(segments.last().unwrap().marker, 0xd9);
+    assert_eq!(segments.last().unwrap().offset, file.len() as u64 - 2);
+    let header = segments
+        .iter()
+        .take_while(|it| it.marker != 0xda)
+        .map(|it| (it.marker, it.data.clone().unwrap()))
+        .collect::<Vec<_>>();
+    let expected = slice::jpeg_segments(&file)
+        .unwrap()
+        .map(|it| it.map(|it| (it.marker, it.data.to_vec())).unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(header, expected);
+
+    let err = jpeg::segments(&mut Cursor::new(&file[..file.len() / 2]))
+        .unwrap()
+        .find_map(Result::err)
+        .unwrap();
+    assert!(err.is_truncated());
+
+    // Cut inside the last segment
+    let first = &segments[1];
+    let cut = &file[..first.offset as usize + 6];
+    let err = jpeg::segments(&mut Cursor::new(cut))
+        .unwrap()
+        .find_map(Result::err)
+        .unwrap();
+    assert_eq!(
+        truncated_at(err),
+        (Some(Block::Marker(first.marker)), Some(first.offset))
+    );
+
+    let limits = Limits {
+        max_blocks: 2,
+        ..Limits::default()
+    };
+    let err = jpeg::segments(&mut Cursor::new(&file))
+        .unwrap()
+        .with_options(limits.into())
+        .find_map(Result::err)
+        .unwrap();
+    assert_eq!(
+        err.corruption_kind(),
+        Some(CorruptionKind::LimitExceeded(Limit::Blocks))
+    );
+
+    // GIF
+    let file = std::fs::read("test-files/paw-animation.gif").unwrap();
+    let blocks = gif::blocks(&mut Cursor::new(&file))
+        .unwrap()
+        .collect::<ImageResult<Vec<_>>>()
+        .unwrap();
+    assert_eq!(
+        blocks.iter().filter(|it| it.label == 0x2c).count(),
+        load_from_buf(&file).unwrap().animation_frames.unwrap()
+    );
+    assert!(blocks
+        .iter()
+        .any(|it| it.extension == Some(0xf9) && it.length == 7));
+    let trailer = blocks.last().unwrap();
+    assert_eq!(
+        (trailer.label, trailer.offset),
+        (0x3b, file.len() as u64 - 1)
+    );
+
+    // Cut inside the last block
+    let last = &blocks[blocks.len() - 2];
+    let err = gif::blocks(&mut Cursor::new(&file[..file.len() - 2]))
+        .unwrap()
+        .find_map(Result::err)
+        .unwrap();
+    assert_eq!(
+        truncated_at(err),
+        (Some(Block::Label(last.label)), Some(last.offset))
+    );
+    let limits = Limits {
+        max_blocks: 2,
+        ..Limits::default()
+    };
+    let err = gif::blocks(&mut Cursor::new(&file))
+        .unwrap()
+        .with_options(limits.into())
+        .find_map(Result::err)
+        .unwrap();
+    assert_eq!(
+        err.corruption_kind(),
+        Some(CorruptionKind::LimitExceeded(Limit::Blocks))
+    );
+
+    // RIFF
+    let file = std::fs::read("test-files/paw-animation.webp").unwrap();
+    let mut cursor = Cursor::new(&file);
+    let mut riff = riff::RiffReader::open(&mut cursor).unwrap();
+    assert_eq!(riff.form_type(), b"WEBP");
+    let mut chunks = vec![];
+    while let Some(chunk) = riff.read_chunk().unwrap() {
+        chunks.push((*chunk.identifier(), chunk.offset(), chunk.size()));
+    }
+    assert_eq!(chunks[0], (*b"VP8X", 12, 10));
+    assert_eq!(
+        chunks.iter().filter(|it| it.0 == *b"ANMF").count(),
+        load_from_buf(&file).unwrap().animation_frames.unwrap()
+    );
+}
+
+#[test]
+fn test_stream_loader() {
+    for suffix in FIXTURES {
+        let file = std::fs::read(format!("test-files/paw{}", suffix)).unwrap();
+        let expected = load_from_buf(&file).unwrap();
+
+        let mut loader = stream::StreamLoader::new();
+        let mut done_at = None;
+        for (i, chunk) in file.chunks(1000).enumerate() {
+            if let stream::Status::Done(meta) = loader.feed(chunk).unwrap() {
+                assert_eq!(meta, expected, "{}", suffix);
+                done_at = Some(i);
+                break;
+            }
+            assert_eq!(loader.dimensions(), Some(DIMS), "{}", suffix);
+        }
+        assert_eq!(loader.format(), Some(expected.format));
+        if suffix == ".jpg" {
+            // Done at SOF before the end
+            assert!((done_at.unwrap() + 1) * 1000 < file.len());
+        }
+        assert_eq!(loader.finish().unwrap(), expected, "{}", suffix);
+    }
+
+    // HDR needs the end of the resolution string
+    let file = b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y 370 +X 507\n";
+    let mut loader = stream::StreamLoader::new();
+    assert_eq!(
+        loader.feed(&file[..file.len() - 1]).unwrap(),
+        stream::Status::NeedMoreData
+    );
+    assert_eq!(loader.dimensions(), None);
+    loader.feed(b"\n").unwrap();
+    assert_eq!(loader.finish().unwrap(), load_from_buf(file).unwrap());
+
+    // Many small chunks in small pieces
+    let file = png_with_many_chunks();
+    let mut loader = stream::StreamLoader::new();
+    let mut chunks = file.chunks(4096);
+    loader.feed(chunks.next().unwrap()).unwrap();
+    assert_eq!(loader.dimensions(), Some(DIMS));
+    for chunk in chunks {
+        loader.feed(chunk).unwrap();
+    }
+    assert_eq!(loader.finish().unwrap(), load_from_buf(&file).unwrap());
+
+    // Too short to detect
+    let mut loader = stream::StreamLoader::new();
+    assert_eq!(loader.feed(b"RIFF").unwrap(), stream::Status::NeedMoreData);
+    assert!(matches!(loader.finish(), Err(ImageError::Unsupported(_))));
+
+    // Truncated
+    let file = std::fs::read("test-files/paw.png").unwrap();
+    let mut loader = stream::StreamLoader::new();
+    assert_eq!(
+        loader.feed(&file[..40]).unwrap(),
+        stream::Status::NeedMoreData
+    );
+    assert!(loader.finish().is_err());
+
+    let file = std::fs::read("test-files/bad.dat").unwrap();
+    let mut loader = stream::StreamLoader::new();
+    assert!(matches!(
+        loader.feed(&file),
+        Err(ImageError::Unsupported(_))
+    ));
+}
+
+#[cfg(feature = "async")]
+#[tokio::test]
+async fn test_load_async() {
+    for suffix in FIXTURES {
+        let path = format!("test-files/paw{}", suffix);
+        let expected = load_from_file(&path).unwrap();
+        assert_eq!(load_from_file_async(&path).await.unwrap(), expected);
+
+        let file = std::fs::read(&path).unwrap();
+        let mut file = std::io::Cursor::new(file);
+        assert_eq!(load_async(&mut file).await.unwrap(), expected);
+    }
+
+    let file = png_with_many_chunks();
+    assert_eq!(
+        load_async(&mut tokio::io::BufReader::new(std::io::Cursor::new(&file)))
+            .await
+            .unwrap(),
+        load_from_buf(&file).unwrap()
+    );
+
+    assert!(matches!(
+        load_from_file_async("test-files/bad.dat").await,
+        Err(ImageError::Unsupported(_))
+    ));
+}
+
+#[test]
+fn test_load_from_reader() {
+    // Reader without Seek returning small pieces
+    struct Pipe<'a>(&'a [u8]);
+
+    impl std::io::Read for Pipe<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let length = buf.len().min(self.0.len()).min(1000);
+            buf[..length].copy_from_slice(&self.0[..length]);
+            self.0 = &self.0[length..];
+            Ok(length)
+        }
+    }
+
+    for suffix in FIXTURES {
+        let file = std::fs::read(format!("test-files/paw{}", suffix)).unwrap();
+        let expected = load_from_buf(&file).unwrap();
+        assert_eq!(load_from_reader(&mut Pipe(&file)).unwrap(), expected);
+        assert_eq!(load_from_reader(&mut &file[..]).unwrap(), expected);
+    }
+
+    let file = std::fs::read("test-files/paw.png").unwrap();
+    assert!(load_from_reader(&mut Pipe(&file[..40])).is_err());
+
+    // Small pieces of many chunks
+    let file = png_with_many_chunks();
+    assert_eq!(
+        load_from_reader(&mut Pipe(&file)).unwrap(),
+        load_from_buf(&file).unwrap()
+    );
+}
+
+#[test]
+fn test_probe() {
+    // File-backed stand-in of remote storage
+    struct Remote {
+        file: File,
+        requests: Vec<(u64, usize)>,
+    }
+
+    impl source::ByteSource for Remote {
+        fn read_at(&mut self, offset: u64, length: usize) -> std::io::Result<Vec<u8>> {
+            self.requests.push((offset, length));
+            self.file.read_at(offset, length)
+        }
+    }
+
+    for suffix in [".bmp", ".gif", ".hdr", ".jpg", ".png", ".qoi", ".webp"] {
+        let path = format!("test-files/paw{}", suffix);
+        let size = std::fs::metadata(&path).unwrap().len();
+        let mut remote = Remote {
+            file: File::open(&path).unwrap(),
+            requests: vec![],
+        };
+
+        let probe = source::probe(&mut remote).unwrap();
+        assert_eq!(probe.meta, load_from_file(&path).unwrap());
+        assert_eq!(
+            probe.requests,
+            remote
+                .requests
+                .iter()
+                .map(|(offset, length)| *offset..offset + *length as u64)
+                .collect::<Vec<_>>()
+        );
+        if suffix == ".gif" {
+            // GIF is scanned to the trailer
+            let fetched: u64 = probe.requests.iter().map(|it| it.end - it.start).sum();
+            assert!(fetched < size);
+        } else {
+            assert_eq!(probe.requests, vec![0..4096], "{}", suffix);
+        }
+    }
+
+    let file = File::open("test-files/paw.png").unwrap();
+    let limits = Limits {
+        max_dimensions: Dimensions {
+            width: 507,
+            height: 369,
+        },
+        ..Limits::default()
+    };
+    assert_eq!(
+        source::probe_with_options(file, &limits.into())
+            .unwrap_err()
+            .corruption_kind(),
+        Some(CorruptionKind::LimitExceeded(Limit::Dimensions))
+    );
+
+    let file = File::open("test-files/bad.dat").unwrap();
+    assert!(matches!(
+        source::probe(file),
+        Err(ImageError::Unsupported(_))
+    ));
+}
@@ -0,0 +1,65 @@
+#![cfg(not(feature = "std"))]
+
+use image_meta::io::{BufRead, Cursor, Read, Seek, SeekFrom};
+use image_meta::*;
+
+const DIMS: Dimensions = Dimensions {
+    width: 507,
+    height: 370,
+};
+
+const FILES: [(&[u8], Format); 8] = [
+    (include_bytes!("../test-files/paw.bmp"), Format::Bmp),
+    (include_bytes!("../test-files/paw.gif"), Format::Gif),
+    (include_bytes!("../test-files/paw.hdr"), Format::Hdr),
+    (include_bytes!("../test-files/paw.jpg"), Format::Jpeg),
+    (include_bytes!("../test-files/paw.png"), Format::Png),
+    (include_bytes!("../test-files/paw.qoi"), Format::Qoi),
+    (include_bytes!("../test-files/paw.webp"), Format::Webp),
+    (
+        include_bytes!("../test-files/paw-animation.gif"),
+        Format::Gif,
+    ),
+];
+
+#[test]
+fn test_load_without_std() {
+    for (file, format) in FILES {
+        let meta = load_from_buf(file).unwrap();
+        assert_eq!(meta.dimensions, DIMS);
+        assert_eq!(meta.format, format);
+
+        assert_eq!(load_from_reader(&mut &file[..]).unwrap(), meta);
+        assert_eq!(detect_format(file), Some(format));
+    }
+
+    assert!(matches!(
+        load_from_buf(include_bytes!("../test-files/bad.dat")),
+        Err(ImageError::Unsupported(_))
+    ));
+    assert!(matches!(
+        load_from_buf(&FILES[0].0[..20]),
+        Err(ImageError::CorruptImage(Corruption {
+            kind: CorruptionKind::Truncated,
+            ..
+        }))
+    ));
+}
+
+#[test]
+fn test_cursor_without_std() {
+    let mut cursor = Cursor::new(&b"0123456789"[..]);
+    let mut buf = [0u8; 3];
+    cursor.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"012");
+    assert_eq!(cursor.seek(SeekFrom::Current(2)).unwrap(), 5);
+    assert_eq!(cursor.fill_buf().unwrap(), b"56789");
+    assert_eq!(cursor.seek(SeekFrom::End(-1)).unwrap(), 9);
+
+    let mut take = (&b"0123456789"[..]).take(4);
+    assert_eq!(take.fill_buf().unwrap(), b"0123");
+    take.consume(3);
+    assert_eq!(take.read(&mut buf).unwrap(), 1);
+    assert_eq!(take.read(&mut buf).unwrap(), 0);
+    assert!(cursor.seek(SeekFrom::Current(-20)).is_err());
+}
@@ -0,0 +1,165 @@
+#![cfg(feature = "std")]
+
+use std::io::Cursor;
+use std::sync::Arc;
+
+use image_meta::registry::{FormatLoader, ImageReader, Registry};
+use image_meta::*;
+
+const XIMG: Format = Format::Custom("Ximg");
+
+// "XIMG", 2 Width, 2 Height
+struct Ximg;
+
+impl FormatLoader for Ximg {
+    fn format(&self) -> Format {
+        XIMG
+    }
+
+    fn detect(&self, signature: &[u8]) -> bool {
+        signature.starts_with(b"XIMG")
+    }
+
+    fn load(&self, image: &mut dyn ImageReader, _: &LoadOptions) -> ImageResult<ImageMeta> {
+        let mut header = [0u8; 8];
+        image.read_exact(&mut header)?;
+        if !self.detect(&header) {
+            return Err(ImageError::InvalidSignature);
+        }
+        Ok(ImageMeta {
+            animation_frames: None,
+            color: Color {
+                alpha_channel: false,
+                mode: ColorMode::Rgb,
+                resolution: 8,
+            },
+            color_space: None,
+            dimensions: Dimensions {
+                width: u32::from(u16::from_le_bytes([header[4], header[5]])),
+                height: u32::from(u16::from_le_bytes([header[6], header[7]])),
+            },
+            format: XIMG,
+        })
+    }
+}
+
+// Takes over PNG to tell the priority
+struct PngOverride;
+
+impl FormatLoader for PngOverride {
+    fn format(&self) -> Format {
+        Format::Custom("PngOverride")
+    }
+
+    fn detect(&self, signature: &[u8]) -> bool {
+        detect_format(signature) == Some(Format::Png)
+    }
+
+    fn load(&self, image: &mut dyn ImageReader, options: &LoadOptions) -> ImageResult<ImageMeta> {
+        let meta = png::load_with_options(image, options)?;
+        Ok(ImageMeta {
+            format: self.format(),
+            ..meta
+        })
+    }
+}
+
+fn tried(err: ImageError) -> Vec<Format> {
+    match err {
+        ImageError::Unsupported(it) => it.tried,
+        err => panic!("{}", err),
+    }
+}
+
+#[test]
+fn test_registry() {
+    let ximg = b"XIMG\x10\x00\x20\x00".to_vec();
+    let png = std::fs::read("test-files/paw.png").unwrap();
+    let qoi = std::fs::read("test-files/paw.qoi").unwrap();
+
+    assert!(load_from_buf(&ximg).is_err());
+    assert_eq!(detect_format(&ximg), None);
+
+    let mut registry = Registry::new();
+    registry.register(Ximg, 0).disable(Format::Qoi);
+    assert_eq!(
+        registry.formats(),
+        vec![
+            Format::Jpeg,
+            Format::Gif,
+            Format::Png,
+            Format::Bmp,
+            Format::Webp,
+            Format::Hdr,
+            XIMG
+        ]
+    );
+
+    let meta = registry
+        .load(&mut Cursor::new(&ximg), &LoadOptions::default())
+        .unwrap();
+    assert_eq!(meta.format, XIMG);
+    assert_eq!(
+        meta.dimensions,
+        Dimensions {
+            width: 16,
+            height: 32
+        }
+    );
+    assert_eq!(registry.detect_format(&ximg), Some(XIMG));
+    assert_eq!(XIMG.to_string(), "Ximg");
+    let meta = registry
+        .load(&mut Cursor::new(&png), &LoadOptions::default())
+        .unwrap();
+    assert_eq!(meta.format, Format::Png);
+
+    // The functions of this crate use the registry of the options
+    let registry = Arc::new(registry);
+    let options = LoadOptions {
+        registry: Some(registry.clone()),
+        ..Default::default()
+    };
+    let meta = load_with_options(&mut Cursor::new(&ximg), &options).unwrap();
+    assert_eq!(meta.format, XIMG);
+    assert_eq!(detect_format_with_options(&ximg, &options), Some(XIMG));
+    let meta = load_from_reader_with_options(&mut &ximg[..], &options).unwrap();
+    assert_eq!(meta.format, XIMG);
+    let err = load_with_options(&mut Cursor::new(&qoi), &options).unwrap_err();
+    assert!(!tried(err).contains(&Format::Qoi));
+
+    // and the built-in formats without one
+    assert!(load_from_buf(&ximg).is_err());
+    assert_eq!(detect_format(&ximg), None);
+    assert_eq!(load_from_buf(&qoi).unwrap().format, Format::Qoi);
+
+    // Disabled format
+    let err = registry
+        .load(&mut Cursor::new(&qoi), &LoadOptions::default())
+        .unwrap_err();
+    assert!(!tried(err).contains(&Format::Qoi));
+    assert_eq!(registry.detect_format(&qoi), None);
+    let err = registry
+        .load_with_format(&mut Cursor::new(&qoi), Format::Qoi, &LoadOptions::default())
+        .unwrap_err();
+    assert_eq!(tried(err), vec![]);
+
+    // Higher priority is tried first
+    let mut registry = Registry::new();
+    registry.register(PngOverride, 1);
+    assert_eq!(registry.formats()[0], Format::Custom("PngOverride"));
+    let meta = registry
+        .load(&mut Cursor::new(&png), &LoadOptions::default())
+        .unwrap();
+    assert_eq!(meta.format, Format::Custom("PngOverride"));
+    assert_eq!(meta.dimensions.width, 507);
+    assert_eq!(
+        registry.detect_format(&png),
+        Some(Format::Custom("PngOverride"))
+    );
+
+    // StreamLoader loads the overridden format with the registry
+    let options = LoadOptions::from(registry);
+    let meta = load_from_reader_with_options(&mut &png[..], &options).unwrap();
+    assert_eq!(meta.format, Format::Custom("PngOverride"));
+    assert_eq!(load_from_reader(&mut &png[..]).unwrap().format, Format::Png);
+}